@@ -0,0 +1,54 @@
+use std::collections::HashMap;
+
+use evdev::EventType;
+use evdev::InputEvent;
+use evdev::Key;
+
+use crate::registry::DeviceId;
+
+/// Buttons of a virtual device and who holds them, changes are collected until the next SYN_REPORT
+#[derive(Default)]
+pub struct Buttons {
+    // button -> device holding it
+    pressed: HashMap<u16, DeviceId>,
+    pending: Vec<InputEvent>,
+}
+
+impl Buttons {
+    pub fn new() -> Buttons {
+        Buttons {
+            pressed: HashMap::new(),
+            pending: Vec::new(),
+        }
+    }
+
+    pub fn set(&mut self, id: DeviceId, key: Key, value: i32) {
+        if value == 0 {
+            self.pressed.remove(&key.code());
+        } else {
+            self.pressed.insert(key.code(), id);
+        }
+        self.pending.push(InputEvent::new(EventType::KEY, key.code(), value));
+    }
+
+    /// Everything collected since the last call, None when nothing changed
+    pub fn take(&mut self) -> Option<Vec<InputEvent>> {
+        if self.pending.is_empty() {
+            None
+        } else {
+            Some(self.pending.drain(..).collect())
+        }
+    }
+
+    /// Lets go of buttons held by `id`, or every button on None
+    pub fn release(&mut self, id: Option<DeviceId>) {
+        let released: Vec<u16> = self.pressed.iter()
+            .filter(|(_, owner)| id.map_or(true, |id| **owner == id))
+            .map(|(code, _)| *code)
+            .collect();
+        for code in released {
+            self.pressed.remove(&code);
+            self.pending.push(InputEvent::new(EventType::KEY, code, 0));
+        }
+    }
+}
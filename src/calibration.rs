@@ -0,0 +1,59 @@
+use std::collections::HashMap;
+
+use evdev::Device;
+use evdev::AbsoluteAxisType;
+
+use crate::remap;
+use crate::output_range;
+use crate::is_hat;
+
+#[derive(Debug, Clone, Copy)]
+struct AxisRange {
+    min: i32,
+    max: i32,
+}
+
+/// Input ranges of every axis a device reports, used to scale them into output ranges
+#[derive(Debug, Default)]
+pub struct Calibration {
+    // keyed by axis code
+    axes: HashMap<u16, AxisRange>,
+}
+
+impl Calibration {
+    pub fn new(dev: &Device) -> Calibration {
+        let mut axes = HashMap::new();
+        if let (Some(supported), Ok(absinfo)) = (dev.supported_absolute_axes(), dev.get_abs_state()) {
+            for axis in supported.iter() {
+                let info = absinfo[axis.0 as usize];
+                axes.insert(axis.0, AxisRange { min: info.minimum, max: info.maximum });
+            }
+        }
+        Calibration { axes }
+    }
+
+    pub fn normalize(&self, axis: AbsoluteAxisType, value: i32) -> i32 {
+        let range = match self.axes.get(&axis.0) {
+            Some(r) if r.min != r.max => r,
+            _ => return value,
+        };
+        let (outmin, outmax) = output_range(axis);
+
+        if range.min == outmin && range.max == outmax {
+            value
+        } else if is_hat(axis) {
+            // hats are digital on the output side, anything past the halfway point counts as pressed
+            let center = range.min + (range.max - range.min) / 2;
+            let threshold = (range.max - range.min) / 4;
+            if value < center - threshold {
+                outmin
+            } else if value > center + threshold {
+                outmax
+            } else {
+                0
+            }
+        } else {
+            remap(value.clamp(range.min, range.max), range.min, range.max, outmin, outmax)
+        }
+    }
+}
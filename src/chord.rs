@@ -0,0 +1,256 @@
+use std::collections::VecDeque;
+use std::time::Duration;
+use std::time::Instant;
+
+use serde::{Deserialize, Serialize};
+
+use evdev::EventType;
+use evdev::InputEvent;
+use evdev::InputEventKind;
+use evdev::Key;
+
+use crate::InputRemap;
+use crate::registry::DeviceId;
+
+/// Event ready for remapping, with the remap to use instead of the table's when it came from a chord
+pub type Chorded = (DeviceId, InputEvent, Option<InputRemap>);
+
+fn default_window() -> u64 {50}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChordConfig {
+    /// Milliseconds all buttons of a chord have to be pressed within
+    #[serde(default = "default_window")]
+    pub window: u64,
+    #[serde(default)]
+    pub rules: Vec<(Vec<Key>, InputRemap)>,
+}
+
+impl Default for ChordConfig {
+    fn default() -> ChordConfig {
+        ChordConfig {
+            window: default_window(),
+            rules: Vec::new(),
+        }
+    }
+}
+
+struct Fired {
+    id: DeviceId,
+    rule: usize,
+    // button the output went out as, so its release matches
+    key: Key,
+    // buttons of the chord still held, their releases get swallowed too
+    keys: Vec<Key>,
+    released: bool,
+}
+
+fn key_event(id: DeviceId, key: Key, value: i32) -> Chorded {
+    (id, InputEvent::new(EventType::KEY, key.code(), value), None)
+}
+
+/// Several buttons held together standing in for a single one
+///
+/// Buttons that are part of a chord are held back for the chord window, when the chord
+/// doesn't come together in time they go out late, otherwise only the chord's output does.
+pub struct Chords {
+    config: ChordConfig,
+    // presses held back, oldest first
+    pending: VecDeque<(DeviceId, Key, Instant)>,
+    fired: Vec<Fired>,
+}
+
+impl Chords {
+    pub fn new(config: ChordConfig) -> Chords {
+        Chords {
+            config,
+            pending: VecDeque::new(),
+            fired: Vec::new(),
+        }
+    }
+
+    fn in_chord(&self, key: Key) -> bool {
+        self.config.rules.iter().any(|(keys, _)| keys.contains(&key))
+    }
+
+    /// When the oldest held back press has to go out
+    pub fn deadline(&self) -> Option<Instant> {
+        self.pending.front().map(|(_, _, deadline)| *deadline)
+    }
+
+    /// Forgets what `id` was holding, whatever it got out is let go of elsewhere
+    pub fn disconnect(&mut self, id: DeviceId) {
+        self.pending.retain(|(owner, _, _)| *owner != id);
+        self.fired.retain(|f| f.id != id);
+    }
+
+    /// Lets out presses whose window ran out
+    pub fn expire(&mut self) -> Vec<Chorded> {
+        let now = Instant::now();
+        let mut out = Vec::new();
+        while let Some((id, key, _)) = self.pending.front().filter(|(_, _, deadline)| *deadline <= now).copied() {
+            self.pending.pop_front();
+            out.push(key_event(id, key, 1));
+        }
+        out
+    }
+
+    pub fn event(&mut self, id: DeviceId, ev: InputEvent) -> Vec<Chorded> {
+        let key = match ev.kind() {
+            InputEventKind::Key(k) if self.in_chord(k) => k,
+            _ => return vec![(id, ev, None)],
+        };
+
+        // anything that ran out goes first, to keep the order
+        let mut out = self.expire();
+        let pending = self.pending.iter().any(|(_, k, _)| *k == key);
+        let swallowed = self.fired.iter().position(|f| f.keys.contains(&key));
+        match ev.value() {
+            0 => if let Some(i) = swallowed {
+                let fired = &mut self.fired[i];
+                fired.keys.retain(|k| *k != key);
+                // letting go of any of the buttons lets go of the chord
+                if !fired.released {
+                    fired.released = true;
+                    let (fid, remap) = (fired.id, self.config.rules[fired.rule].1.clone());
+                    out.push((fid, InputEvent::new(EventType::KEY, fired.key.code(), 0), Some(remap)));
+                }
+                if self.fired[i].keys.is_empty() {
+                    self.fired.remove(i);
+                }
+            } else if pending {
+                // a tap shorter than the window, it wasn't a chord
+                while let Some((pid, k, _)) = self.pending.pop_front() {
+                    out.push(key_event(pid, k, 1));
+                    if k == key {
+                        break;
+                    }
+                }
+                out.push((id, ev, None));
+            } else {
+                out.push((id, ev, None));
+            },
+            1 => if swallowed.is_none() && !pending {
+                let window = Duration::from_millis(self.config.window);
+                self.pending.push_back((id, key, Instant::now() + window));
+                if let Some(fired) = self.complete() {
+                    out.push(fired);
+                }
+            },
+            // repeats of held back or swallowed buttons don't go anywhere
+            _ => if swallowed.is_none() && !pending {
+                out.push((id, ev, None));
+            },
+        }
+        out
+    }
+
+    /// Fires the biggest chord the held back presses make up, if any
+    fn complete(&mut self) -> Option<Chorded> {
+        let (rule, (keys, remap)) = self.config.rules.iter().enumerate()
+            .filter(|(_, (keys, _))| keys.iter().all(|k| self.pending.iter().any(|(_, p, _)| p == k)))
+            .max_by_key(|(_, (keys, _))| keys.len())?;
+
+        let (id, last, _) = *self.pending.back()?;
+        self.pending.retain(|(_, k, _)| !keys.contains(k));
+        self.fired.push(Fired { id, rule, key: last, keys: keys.clone(), released: false });
+        Some((id, InputEvent::new(EventType::KEY, last.code(), 1), Some(remap.clone())))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::thread;
+
+    fn chords(window: u64, rules: &[(&[Key], Key)]) -> Chords {
+        Chords::new(ChordConfig {
+            window,
+            rules: rules.iter().map(|(keys, out)| (keys.to_vec(), InputRemap::Key(*out))).collect(),
+        })
+    }
+
+    fn key(k: Key, value: i32) -> InputEvent {
+        InputEvent::new(EventType::KEY, k.code(), value)
+    }
+
+    /// Button, value and chord output of each event
+    fn keys(out: &[Chorded]) -> Vec<(Key, i32, Option<InputRemap>)> {
+        out.iter().map(|(_, ev, chord)| (Key::new(ev.code()), ev.value(), chord.clone())).collect()
+    }
+
+    #[test]
+    fn held_back_for_window() {
+        let mut c = chords(1000, &[(&[Key::BTN_SELECT, Key::BTN_START], Key::BTN_MODE)]);
+        let id = DeviceId::next();
+
+        assert!(c.event(id, key(Key::BTN_SELECT, 1)).is_empty());
+        assert!(c.deadline().is_some());
+        // buttons outside any chord don't wait
+        assert_eq!(keys(&c.event(id, key(Key::BTN_SOUTH, 1))), [(Key::BTN_SOUTH, 1, None)]);
+        assert!(c.expire().is_empty());
+    }
+
+    #[test]
+    fn expires_oldest_first() {
+        let mut c = chords(50, &[
+            (&[Key::BTN_SELECT, Key::BTN_START], Key::BTN_MODE),
+            (&[Key::BTN_TL, Key::BTN_TR], Key::BTN_THUMBL),
+        ]);
+        let (a, b) = (DeviceId::next(), DeviceId::next());
+
+        assert!(c.event(a, key(Key::BTN_TR, 1)).is_empty());
+        assert!(c.event(b, key(Key::BTN_SELECT, 1)).is_empty());
+        thread::sleep(Duration::from_millis(60));
+
+        let out = c.expire();
+        assert_eq!(keys(&out), [(Key::BTN_TR, 1, None), (Key::BTN_SELECT, 1, None)]);
+        assert_eq!(out.iter().map(|(id, _, _)| *id).collect::<Vec<_>>(), [a, b]);
+        assert!(c.deadline().is_none());
+    }
+
+    #[test]
+    fn tap_shorter_than_window() {
+        let mut c = chords(1000, &[(&[Key::BTN_SELECT, Key::BTN_START], Key::BTN_MODE)]);
+        let id = DeviceId::next();
+
+        assert!(c.event(id, key(Key::BTN_SELECT, 1)).is_empty());
+        assert_eq!(keys(&c.event(id, key(Key::BTN_SELECT, 0))), [(Key::BTN_SELECT, 1, None), (Key::BTN_SELECT, 0, None)]);
+        assert!(c.deadline().is_none());
+    }
+
+    #[test]
+    fn swallows_chord_releases() {
+        let mut c = chords(1000, &[(&[Key::BTN_SELECT, Key::BTN_START], Key::BTN_MODE)]);
+        let id = DeviceId::next();
+        let mode = Some(InputRemap::Key(Key::BTN_MODE));
+
+        assert!(c.event(id, key(Key::BTN_SELECT, 1)).is_empty());
+        assert_eq!(keys(&c.event(id, key(Key::BTN_START, 1))), [(Key::BTN_START, 1, mode.clone())]);
+        assert!(c.deadline().is_none());
+
+        // repeats go nowhere, the first release lets go of the output, the rest are swallowed
+        assert!(c.event(id, key(Key::BTN_SELECT, 2)).is_empty());
+        assert_eq!(keys(&c.event(id, key(Key::BTN_SELECT, 0))), [(Key::BTN_START, 0, mode)]);
+        assert!(c.event(id, key(Key::BTN_START, 0)).is_empty());
+
+        // and afterwards the buttons are held back again
+        assert!(c.event(id, key(Key::BTN_START, 1)).is_empty());
+    }
+
+    #[test]
+    fn biggest_chord_wins() {
+        let mut c = chords(1000, &[
+            (&[Key::BTN_SELECT, Key::BTN_START], Key::BTN_MODE),
+            (&[Key::BTN_SELECT, Key::BTN_START, Key::BTN_TL], Key::BTN_THUMBL),
+        ]);
+        let id = DeviceId::next();
+
+        assert!(c.event(id, key(Key::BTN_SELECT, 1)).is_empty());
+        assert!(c.event(id, key(Key::BTN_TL, 1)).is_empty());
+        let out = c.event(id, key(Key::BTN_START, 1));
+        assert_eq!(keys(&out), [(Key::BTN_START, 1, Some(InputRemap::Key(Key::BTN_THUMBL)))]);
+        assert!(c.deadline().is_none());
+    }
+}
@@ -0,0 +1,88 @@
+use serde::{Deserialize, Serialize};
+
+use evdev::AbsoluteAxisType;
+
+use crate::output_range;
+use crate::is_hat;
+
+/// Shapes an axis, all curves map [0, 1] onto [0, 1] and work on distance from rest position
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum ResponseCurve {
+    Linear,
+    /// `t^exponent`, above 1 gives more precision near center
+    Power(f32),
+    /// `(e^(k*t) - 1) / (e^k - 1)`, positive k is slow near center, negative k is fast
+    Exponential(f32),
+    /// Sigmoid through the middle point, above 1 flattens both ends
+    SCurve(f32),
+    /// Points (input, output), linearly interpolated in between
+    Table(Vec<(f32, f32)>),
+}
+
+impl ResponseCurve {
+    pub fn eval(&self, t: f32) -> f32 {
+        let t = t.clamp(0.0, 1.0);
+        let out = match self {
+            ResponseCurve::Linear => t,
+            ResponseCurve::Power(exp) => t.powf(*exp),
+            ResponseCurve::Exponential(k) => {
+                if k.abs() < f32::EPSILON {
+                    t
+                } else {
+                    ((k * t).exp() - 1.0) / (k.exp() - 1.0)
+                }
+            },
+            ResponseCurve::SCurve(k) => {
+                let a = t.powf(*k);
+                let b = (1.0 - t).powf(*k);
+                if a + b == 0.0 { t } else { a / (a + b) }
+            },
+            ResponseCurve::Table(points) => interpolate(points, t),
+        };
+        out.clamp(0.0, 1.0)
+    }
+
+    /// Applies the curve to a value that is already in output range of `axis`
+    pub fn apply(&self, axis: AbsoluteAxisType, value: i32) -> i32 {
+        // hats are digital, nothing to shape
+        if is_hat(axis) {
+            return value;
+        }
+
+        let (min, max) = output_range(axis);
+        if min < 0 {
+            // centered axis, shape both halves the same way
+            let limit = if value < 0 { -(min as f32) } else { max as f32 };
+            let t = value.abs() as f32 / limit;
+            (self.eval(t) * limit).round().copysign(value as f32) as i32
+        } else {
+            let range = (max - min) as f32;
+            let t = (value - min) as f32 / range;
+            min + (self.eval(t) * range).round() as i32
+        }
+    }
+}
+
+fn interpolate(points: &[(f32, f32)], t: f32) -> f32 {
+    let (first, last) = match (points.first(), points.last()) {
+        (Some(f), Some(l)) => (f, l),
+        _ => return t,
+    };
+    if t <= first.0 {
+        return first.1;
+    }
+    if t >= last.0 {
+        return last.1;
+    }
+
+    for pair in points.windows(2) {
+        let ((x0, y0), (x1, y1)) = (pair[0], pair[1]);
+        if t >= x0 && t <= x1 {
+            if x1 - x0 <= f32::EPSILON {
+                return y1;
+            }
+            return y0 + (t - x0) * (y1 - y0) / (x1 - x0);
+        }
+    }
+    t
+}
@@ -0,0 +1,126 @@
+use serde::{Deserialize, Serialize};
+
+use evdev::AbsoluteAxisType;
+
+use crate::MAX_OUT_ANALOG;
+use crate::MIN_OUT_ANALOG;
+
+fn one() -> f32 {1.0}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub enum DeadzoneShape {
+    /// Each axis is filtered on its own, gives a square deadzone
+    Axial,
+    /// Filters on distance from center, keeps the stick angle intact
+    #[default]
+    Radial,
+}
+
+/// All values are fractions of full stick deflection
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct Deadzone {
+    #[serde(default)]
+    pub shape: DeadzoneShape,
+    /// Anything closer to center than this reads as centered
+    #[serde(default)]
+    pub inner: f32,
+    /// Anything further than this reads as full deflection
+    #[serde(default = "one")]
+    pub outer: f32,
+    /// Smallest output past the inner deadzone, cancels out deadzones games apply on their own
+    #[serde(default)]
+    pub anti: f32,
+}
+
+impl Deadzone {
+    fn scale(&self, magnitude: f32) -> f32 {
+        if magnitude <= self.inner {
+            return 0.0;
+        }
+        let range = (self.outer - self.inner).max(f32::EPSILON);
+        let scaled = ((magnitude - self.inner) / range).min(1.0);
+        self.anti + scaled * (1.0 - self.anti)
+    }
+
+    fn apply(&self, x: f32, y: f32) -> (f32, f32) {
+        match self.shape {
+            DeadzoneShape::Axial => (self.scale(x.abs()).copysign(x), self.scale(y.abs()).copysign(y)),
+            DeadzoneShape::Radial => {
+                let magnitude = x.hypot(y);
+                if magnitude == 0.0 {
+                    return (0.0, 0.0);
+                }
+                let factor = self.scale(magnitude) / magnitude;
+                (x * factor, y * factor)
+            },
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct StickDeadzones {
+    #[serde(default)]
+    pub left: Option<Deadzone>,
+    #[serde(default)]
+    pub right: Option<Deadzone>,
+}
+
+#[inline]
+fn to_unit(v: i32) -> f32 {
+    (v as f32 / MAX_OUT_ANALOG as f32).clamp(-1.0, 1.0)
+}
+
+#[inline]
+fn from_unit(v: f32) -> i32 {
+    ((v * MAX_OUT_ANALOG as f32).round() as i32).clamp(MIN_OUT_ANALOG, MAX_OUT_ANALOG)
+}
+
+struct Stick {
+    deadzone: Deadzone,
+    x_axis: AbsoluteAxisType,
+    y_axis: AbsoluteAxisType,
+    x: i32,
+    y: i32,
+}
+
+impl Stick {
+    fn new(deadzone: Deadzone, x_axis: AbsoluteAxisType, y_axis: AbsoluteAxisType) -> Stick {
+        Stick { deadzone, x_axis, y_axis, x: 0, y: 0 }
+    }
+
+    fn update(&mut self, axis: AbsoluteAxisType, value: i32) -> Option<[(AbsoluteAxisType, i32); 2]> {
+        if axis == self.x_axis {
+            self.x = value;
+        } else if axis == self.y_axis {
+            self.y = value;
+        } else {
+            return None;
+        }
+
+        let (x, y) = self.deadzone.apply(to_unit(self.x), to_unit(self.y));
+        Some([(self.x_axis, from_unit(x)), (self.y_axis, from_unit(y))])
+    }
+}
+
+/// Per-device deadzone state, works on already calibrated values
+pub struct Sticks {
+    sticks: Vec<Stick>,
+}
+
+impl Sticks {
+    pub fn new(config: &StickDeadzones) -> Sticks {
+        let mut sticks = Vec::new();
+        if let Some(dz) = config.left {
+            sticks.push(Stick::new(dz, AbsoluteAxisType::ABS_X, AbsoluteAxisType::ABS_Y));
+        }
+        if let Some(dz) = config.right {
+            sticks.push(Stick::new(dz, AbsoluteAxisType::ABS_RX, AbsoluteAxisType::ABS_RY));
+        }
+        Sticks { sticks }
+    }
+
+    /// Returns new values of both axes of the stick, or None if `axis` isn't filtered
+    pub fn process(&mut self, axis: AbsoluteAxisType, value: i32) -> Option<[(AbsoluteAxisType, i32); 2]> {
+        self.sticks.iter_mut().find_map(|s| s.update(axis, value))
+    }
+}
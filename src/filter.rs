@@ -0,0 +1,167 @@
+use serde::{Deserialize, Serialize};
+
+use evdev::Device;
+use evdev::Key;
+use evdev::AbsoluteAxisType;
+use evdev::BusType;
+use evdev::InputId;
+
+use crate::registry::DeviceInfo;
+
+/// Shell-style glob, supports `*` and `?`
+pub fn glob_match(pattern: &str, s: &str) -> bool {
+    let p: Vec<char> = pattern.chars().collect();
+    let s: Vec<char> = s.chars().collect();
+    let (mut pi, mut si) = (0, 0);
+    let mut backtrack: Option<(usize, usize)> = None;
+
+    while si < s.len() {
+        if pi < p.len() && (p[pi] == '?' || p[pi] == s[si]) {
+            pi += 1;
+            si += 1;
+        } else if pi < p.len() && p[pi] == '*' {
+            backtrack = Some((pi, si));
+            pi += 1;
+        } else if let Some((bp, bs)) = backtrack {
+            pi = bp + 1;
+            si = bs + 1;
+            backtrack = Some((bp, bs + 1));
+        } else {
+            return false;
+        }
+    }
+
+    p[pi..].iter().all(|c| *c == '*')
+}
+
+/// Describes a set of devices, every field that is set has to match
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct DeviceMatch {
+    #[serde(default)]
+    pub name: Option<String>,
+    #[serde(default)]
+    pub vendor: Option<u16>,
+    #[serde(default)]
+    pub product: Option<u16>,
+    #[serde(default)]
+    pub version: Option<u16>,
+    #[serde(default)]
+    pub bus: Option<BusType>,
+    #[serde(default)]
+    pub phys: Option<String>,
+    #[serde(default)]
+    pub has_keys: Vec<Key>,
+    #[serde(default)]
+    pub lacks_keys: Vec<Key>,
+    #[serde(default)]
+    pub has_axes: Vec<AbsoluteAxisType>,
+    #[serde(default)]
+    pub lacks_axes: Vec<AbsoluteAxisType>,
+}
+
+impl DeviceMatch {
+    fn matches_parts(&self, name: &str, phys: &str, id: &InputId,
+                     has_key: impl Fn(&Key) -> bool, has_axis: impl Fn(&AbsoluteAxisType) -> bool) -> bool {
+        self.name.as_ref().map_or(true, |n| glob_match(n, name))
+            && self.phys.as_ref().map_or(true, |p| glob_match(p, phys))
+            && self.vendor.map_or(true, |v| v == id.vendor())
+            && self.product.map_or(true, |p| p == id.product())
+            && self.version.map_or(true, |v| v == id.version())
+            && self.bus.map_or(true, |b| b == id.bus_type())
+            && self.has_keys.iter().all(&has_key)
+            && !self.lacks_keys.iter().any(&has_key)
+            && self.has_axes.iter().all(&has_axis)
+            && !self.lacks_axes.iter().any(&has_axis)
+    }
+
+    pub fn matches(&self, dev: &Device) -> bool {
+        let keys = dev.supported_keys();
+        let axes = dev.supported_absolute_axes();
+        self.matches_parts(dev.name().unwrap_or(""), dev.physical_path().unwrap_or(""), &dev.input_id(),
+            |k| keys.map_or(false, |keys| keys.contains(*k)),
+            |a| axes.map_or(false, |axes| axes.contains(*a)))
+    }
+
+    /// Same as `matches`, for devices that are already in the registry
+    pub fn matches_info(&self, info: &DeviceInfo) -> bool {
+        self.matches_parts(&info.name, info.phys.as_deref().unwrap_or(""), &info.input_id,
+            |k| info.keys.contains(k),
+            |a| info.axes.contains(a))
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum FilterRule {
+    Include(DeviceMatch),
+    Exclude(DeviceMatch),
+}
+
+pub fn default_rules() -> Vec<FilterRule> {
+    vec![
+        // gamepads
+        FilterRule::Include(DeviceMatch {
+            has_keys: vec![Key::BTN_SOUTH],
+            ..Default::default()
+        }),
+        // extra buttons on x86 handhelds
+        FilterRule::Include(DeviceMatch {
+            has_keys: vec![Key::KEY_LEFTMETA],
+            bus: Some(BusType::BUS_I8042),
+            ..Default::default()
+        }),
+        // touchscreens
+        FilterRule::Exclude(DeviceMatch {
+            has_keys: vec![Key::BTN_TOUCH],
+            ..Default::default()
+        }),
+        // steam input, note the space
+        FilterRule::Exclude(DeviceMatch {
+            name: Some("Microsoft X-Box 360 pad *".to_string()),
+            ..Default::default()
+        }),
+        // devices without a name
+        FilterRule::Exclude(DeviceMatch {
+            name: Some("".to_string()),
+            ..Default::default()
+        }),
+    ]
+}
+
+/// Rules are checked in order, the last one that matches decides
+pub fn is_useful(rules: &[FilterRule], dev: &Device) -> bool {
+    let mut useful = false;
+    for rule in rules {
+        match rule {
+            FilterRule::Include(m) => if m.matches(dev) { useful = true },
+            FilterRule::Exclude(m) => if m.matches(dev) { useful = false },
+        }
+    }
+    useful
+}
+
+/// Whether the last rule matching `dev` is an `Exclude`, for devices that don't need an `Include`
+pub fn is_excluded(rules: &[FilterRule], dev: &Device) -> bool {
+    rules.iter().rev()
+        .find_map(|rule| match rule {
+            FilterRule::Include(m) => m.matches(dev).then_some(false),
+            FilterRule::Exclude(m) => m.matches(dev).then_some(true),
+        })
+        .unwrap_or(false)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn glob() {
+        assert!(glob_match("Microsoft X-Box 360 pad *", "Microsoft X-Box 360 pad 0"));
+        assert!(!glob_match("Microsoft X-Box 360 pad *", "Microsoft X-Box 360 pad"));
+        assert!(glob_match("usb-*-1/input?", "usb-0000:00:14.0-1/input0"));
+        assert!(!glob_match("usb-*-1/input?", "usb-0000:00:14.0-1/input10"));
+        assert!(glob_match("*a*b", "xxaxxab"));
+        assert!(glob_match("*", ""));
+        assert!(glob_match("", ""));
+        assert!(!glob_match("", "a"));
+    }
+}
@@ -0,0 +1,170 @@
+use std::collections::HashMap;
+use std::time::Instant;
+
+use serde::{Deserialize, Serialize};
+
+use evdev::AbsoluteAxisType;
+
+use crate::motion::MotionFrame;
+use crate::motion::GYRO_RANGE;
+use crate::motion::GYRO_RES;
+use crate::registry::DeviceId;
+use crate::transform::Stick;
+use crate::MAX_OUT_ANALOG;
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub enum FlickOutput {
+    /// Pixels per degree turned, on the virtual mouse
+    Mouse(f32),
+    /// Turning rides on the yaw of the pad's motion sensors, for games that aim with gyro
+    Motion,
+}
+
+fn default_stick() -> Stick {Stick::Right}
+fn default_threshold() -> f32 {0.9}
+fn default_flick_time() -> f32 {0.1}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FlickConfig {
+    #[serde(default = "default_stick")]
+    pub stick: Stick,
+    pub output: FlickOutput,
+    /// Deflection, 0 to 1, where a flick starts
+    #[serde(default = "default_threshold")]
+    pub threshold: f32,
+    /// Seconds a flick is spread over on Motion output, mouse turns at once
+    #[serde(default = "default_flick_time")]
+    pub flick_time: f32,
+}
+
+#[derive(Default)]
+struct StickState {
+    x: f32,
+    y: f32,
+    // where the stick pointed on the last report, None while it's under the threshold
+    angle: Option<f32>,
+}
+
+#[derive(Default)]
+struct TurnState {
+    // degrees to turn on the next sensor report
+    turn: f32,
+    // flick in progress, degrees and seconds left
+    flick: Option<(f32, f32)>,
+    last: Option<Instant>,
+}
+
+/// Clockwise is positive, -180 to 180
+fn wrap(degrees: f32) -> f32 {
+    (degrees + 180.0).rem_euclid(360.0) - 180.0
+}
+
+/// Turns the camera by where the stick points instead of deflecting a stick
+///
+/// Pushing the stick out turns by its angle from straight up, rotating it while it's out keeps turning.
+pub struct FlickStick {
+    config: Option<FlickConfig>,
+    sticks: HashMap<DeviceId, StickState>,
+    // player slot -> turning left to put on its motion sensors
+    turns: HashMap<usize, TurnState>,
+}
+
+impl FlickStick {
+    pub fn new(config: Option<FlickConfig>) -> FlickStick {
+        FlickStick {
+            config,
+            sticks: HashMap::new(),
+            turns: HashMap::new(),
+        }
+    }
+
+    /// Whether `axis` is taken over, it doesn't reach the pad then
+    pub fn handles(&self, axis: AbsoluteAxisType) -> bool {
+        self.config.as_ref().map_or(false, |c| {
+            let (x_axis, y_axis) = c.stick.axes();
+            axis == x_axis || axis == y_axis
+        })
+    }
+
+    pub fn axis(&mut self, id: DeviceId, axis: AbsoluteAxisType, value: i32) {
+        let config = match self.config {
+            Some(ref c) => c,
+            None => return,
+        };
+        let state = self.sticks.entry(id).or_default();
+        let v = value as f32 / MAX_OUT_ANALOG as f32;
+        if axis == config.stick.axes().0 {
+            state.x = v;
+        } else {
+            state.y = v;
+        }
+    }
+
+    pub fn disconnect(&mut self, id: DeviceId) {
+        self.sticks.remove(&id);
+    }
+
+    /// Works out the turn once the whole report of `id` is in, returns pixels to move sideways on Mouse output
+    pub fn sync(&mut self, id: DeviceId, slot: usize) -> Option<f32> {
+        let config = self.config.as_ref()?;
+        let state = self.sticks.get_mut(&id)?;
+
+        if state.x.hypot(state.y) < config.threshold {
+            state.angle = None;
+            return None;
+        }
+        // straight up is 0, stick up is negative
+        let angle = state.x.atan2(-state.y).to_degrees();
+        let (flick, turn) = match state.angle.replace(angle) {
+            None => (angle, 0.0),
+            Some(prev) => (0.0, wrap(angle - prev)),
+        };
+
+        match config.output {
+            FlickOutput::Mouse(per_degree) => Some((flick + turn) * per_degree),
+            FlickOutput::Motion => {
+                let t = self.turns.entry(slot).or_default();
+                t.turn += turn;
+                if flick != 0.0 {
+                    // a new flick doesn't wait for the last one
+                    if let Some((left, _)) = t.flick.take() {
+                        t.turn += left;
+                    }
+                    t.flick = Some((flick, config.flick_time));
+                }
+                None
+            },
+        }
+    }
+
+    /// Adds turning to a sensor report of `slot` on its way out
+    pub fn motion(&mut self, slot: usize, frame: &mut MotionFrame) {
+        match self.config {
+            Some(FlickConfig { output: FlickOutput::Motion, .. }) => (),
+            _ => return,
+        }
+        let state = self.turns.entry(slot).or_default();
+        let now = Instant::now();
+        let dt = state.last.replace(now).map_or(0.0, |last| now.duration_since(last).as_secs_f32());
+        if dt <= 0.0 || dt > 0.1 {
+            return;
+        }
+
+        let mut degrees = std::mem::take(&mut state.turn);
+        if let Some((left, time)) = state.flick.take() {
+            let share = (dt / time).min(1.0);
+            degrees += left * share;
+            if share < 1.0 {
+                state.flick = Some((left * (1.0 - share), time - dt));
+            }
+        }
+        // whatever is over the sensor's range waits for the next report
+        let max = GYRO_RANGE as f32 / GYRO_RES as f32 * dt;
+        let now_degrees = degrees.clamp(-max, max);
+        state.turn = degrees - now_degrees;
+
+        // yaw is counter-clockwise, turning right is negative
+        let yaw = frame.gyro[1] as f32 - now_degrees / dt * GYRO_RES as f32;
+        frame.gyro[1] = (yaw.round() as i32).clamp(-GYRO_RANGE, GYRO_RANGE);
+    }
+}
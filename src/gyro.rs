@@ -0,0 +1,157 @@
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::time::Instant;
+
+use serde::{Deserialize, Serialize};
+
+use evdev::Key;
+use evdev::AbsoluteAxisType;
+
+use crate::motion::MotionFrame;
+use crate::motion::GYRO_RES;
+use crate::registry::DeviceId;
+use crate::transform::Stick;
+use crate::MAX_OUT_ANALOG;
+use crate::MIN_OUT_ANALOG;
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub enum GyroOutput {
+    /// Pixels per degree turned
+    Mouse(f32),
+    /// Turning speed in degrees per second that gives full deflection
+    Stick(Stick, f32),
+}
+
+/// Which rotation turns the camera sideways
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub enum GyroYaw {
+    /// Turning the pad around its vertical axis, like a steering wheel lying flat
+    #[default]
+    Yaw,
+    /// Tilting the pad left and right, like a steering wheel
+    Roll,
+}
+
+fn default_smoothing() -> f32 {0.02}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GyroConfig {
+    pub output: GyroOutput,
+    #[serde(default)]
+    pub horizontal: GyroYaw,
+    /// Time constant of smoothing in seconds, 0 turns it off
+    #[serde(default = "default_smoothing")]
+    pub smoothing: f32,
+    /// Gyro only works while this is held, always on when left out
+    #[serde(default)]
+    pub activation: Option<Key>,
+    #[serde(default)]
+    pub invert_x: bool,
+    #[serde(default)]
+    pub invert_y: bool,
+}
+
+pub enum GyroEvent {
+    Abs(AbsoluteAxisType, i32),
+    /// Pixels to move the pointer by
+    Mouse(f32, f32),
+}
+
+#[derive(Default)]
+struct SensorState {
+    last: Option<(Instant, Option<i32>)>,
+    // smoothed angular velocity, degrees per second
+    velocity: (f32, f32),
+    // stick is away from center, has to be brought back when gyro gets turned off
+    deflected: bool,
+}
+
+/// Turns angular velocity of motion sensors into stick deflection or pointer motion
+pub struct Gyro {
+    config: Option<GyroConfig>,
+    held: HashSet<Key>,
+    sensors: HashMap<DeviceId, SensorState>,
+}
+
+impl Gyro {
+    pub fn new(config: Option<GyroConfig>) -> Gyro {
+        Gyro {
+            config,
+            held: HashSet::new(),
+            sensors: HashMap::new(),
+        }
+    }
+
+    /// Feeds a button before any remapping, for the activation button
+    pub fn key(&mut self, key: Key, value: i32) {
+        if value == 0 {
+            self.held.remove(&key);
+        } else {
+            self.held.insert(key);
+        }
+    }
+
+    pub fn disconnect(&mut self, id: DeviceId) {
+        self.sensors.remove(&id);
+    }
+
+    pub fn process(&mut self, id: DeviceId, frame: &MotionFrame) -> Vec<GyroEvent> {
+        let config = match self.config {
+            Some(ref c) => c,
+            None => return Vec::new(),
+        };
+        let state = self.sensors.entry(id).or_default();
+
+        // prefer the sensor's own clock, it doesn't care about how late we got to the frame
+        let now = Instant::now();
+        let dt = match (state.last, frame.timestamp) {
+            (Some((_, Some(prev))), Some(ts)) => ts.wrapping_sub(prev) as f32 / 1_000_000.0,
+            (Some((prev, _)), _) => now.duration_since(prev).as_secs_f32(),
+            (None, _) => 0.0,
+        };
+        state.last = Some((now, frame.timestamp));
+        // after a pause there's nothing sensible to integrate over
+        if dt <= 0.0 || dt > 0.1 {
+            return Vec::new();
+        }
+
+        // counter-clockwise is positive, flipped so that turning right and tilting down go right and down
+        let dps = |v: i32| v as f32 / GYRO_RES as f32;
+        let mut x = -dps(match config.horizontal {
+            GyroYaw::Yaw => frame.gyro[1],
+            GyroYaw::Roll => frame.gyro[2],
+        });
+        let mut y = -dps(frame.gyro[0]);
+        if config.invert_x {
+            x = -x;
+        }
+        if config.invert_y {
+            y = -y;
+        }
+
+        let alpha = if config.smoothing > 0.0 { (dt / (config.smoothing + dt)).min(1.0) } else { 1.0 };
+        state.velocity.0 += (x - state.velocity.0) * alpha;
+        state.velocity.1 += (y - state.velocity.1) * alpha;
+        let (vx, vy) = state.velocity;
+
+        let active = config.activation.map_or(true, |k| self.held.contains(&k));
+        match config.output {
+            GyroOutput::Mouse(_) if !active => Vec::new(),
+            GyroOutput::Mouse(per_degree) => vec![GyroEvent::Mouse(vx * dt * per_degree, vy * dt * per_degree)],
+            GyroOutput::Stick(stick, full_speed) => {
+                let (x_axis, y_axis) = stick.axes();
+                if !active {
+                    if !state.deflected {
+                        return Vec::new();
+                    }
+                    state.deflected = false;
+                    return vec![GyroEvent::Abs(x_axis, 0), GyroEvent::Abs(y_axis, 0)];
+                }
+                let deflect = |v: f32| ((v / full_speed) * MAX_OUT_ANALOG as f32).round()
+                    .clamp(MIN_OUT_ANALOG as f32, MAX_OUT_ANALOG as f32) as i32;
+                state.deflected = true;
+                vec![GyroEvent::Abs(x_axis, deflect(vx)), GyroEvent::Abs(y_axis, deflect(vy))]
+            },
+        }
+    }
+}
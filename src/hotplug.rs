@@ -0,0 +1,117 @@
+use std::thread;
+use std::sync::mpsc::Sender;
+use std::sync::{Arc, Mutex};
+use std::collections::HashMap;
+use std::path::Path;
+use std::path::PathBuf;
+
+use anyhow::Result;
+use anyhow::Context;
+use nix::sys::inotify::{AddWatchFlags, InitFlags, Inotify};
+
+use evdev::Device;
+
+use crate::RinputerEvent;
+use crate::input_handler;
+use crate::registry::DeviceId;
+use crate::registry::DeviceInfo;
+use crate::HandlerConfig;
+
+static INPUT_DIR: &str = "/dev/input";
+
+// node -> whether it has a handler, nodes that were turned down stay until they're deleted
+type OpenDevices = Arc<Mutex<HashMap<PathBuf, bool>>>;
+
+/// Tells the main loop about a device, and that it's gone once this is dropped
+pub struct Connection {
+    tx: Sender<RinputerEvent>,
+    id: DeviceId,
+}
+
+pub fn connect(tx: &Sender<RinputerEvent>, id: DeviceId, info: DeviceInfo) -> Result<Connection> {
+    tx.send(RinputerEvent::DeviceConnected(id, info))?;
+    Ok(Connection { tx: tx.clone(), id })
+}
+
+impl Drop for Connection {
+    fn drop(&mut self) {
+        let _ = self.tx.send(RinputerEvent::DeviceDisconnected(self.id));
+    }
+}
+
+fn spawn_handler(tx: &Sender<RinputerEvent>, open: &OpenDevices, config: &Arc<HandlerConfig>, path: PathBuf, dev: Device) {
+    // every node gets at most one handler
+    if open.lock().unwrap().insert(path.clone(), true).is_some() {
+        return;
+    }
+
+    let new_tx = tx.clone();
+    let open = open.clone();
+    let config = config.clone();
+    let id = DeviceId::next();
+    thread::spawn(move || {
+        match input_handler(new_tx, id, &path, dev, &config) {
+            // handlers only return Ok for devices they didn't take, keeping the node around
+            // stops every IN_ATTRIB on it from opening it again
+            Ok(()) => { open.lock().unwrap().insert(path, false); },
+            Err(e) => {
+                println!("Device {} went away: {}", path.display(), e);
+                open.lock().unwrap().remove(&path);
+            },
+        }
+    });
+}
+
+fn is_event_node(name: &std::ffi::OsStr) -> bool {
+    name.to_str().map_or(false, |n| n.starts_with("event"))
+}
+
+/// Sets up the watch on /dev/input, done before `indev_watcher` starts so failing here is fatal
+pub fn watch_input() -> Result<Inotify> {
+    let inotify = Inotify::init(InitFlags::empty())
+        .context("Failed to initialize inotify")?;
+    // udev creates the node first and fixes up permissions afterwards, so opening on
+    // IN_CREATE alone can fail, IN_ATTRIB gives us a second chance, IN_DELETE forgets turned down nodes
+    inotify.add_watch(INPUT_DIR, AddWatchFlags::IN_CREATE | AddWatchFlags::IN_ATTRIB | AddWatchFlags::IN_DELETE)
+        .with_context(|| format!("Failed to watch {}", INPUT_DIR))?;
+    Ok(inotify)
+}
+
+pub fn indev_watcher(inotify: Inotify, tx: Sender<RinputerEvent>, config: HandlerConfig) -> Result<()> {
+    let open: OpenDevices = Arc::new(Mutex::new(HashMap::new()));
+    let config = Arc::new(config);
+
+    // the watch is already set up, so nothing plugged in while enumerating gets missed
+    for (path, dev) in evdev::enumerate() {
+        spawn_handler(&tx, &open, &config, path, dev);
+    }
+
+    loop {
+        for event in inotify.read_events().context("Failed to read inotify events")? {
+            let name = match event.name {
+                Some(n) => n,
+                None => continue,
+            };
+            if !is_event_node(&name) {
+                continue;
+            }
+
+            let path = Path::new(INPUT_DIR).join(name);
+            if event.mask.contains(AddWatchFlags::IN_DELETE) {
+                // ones with a handler are freed when the handler exits
+                let mut open = open.lock().unwrap();
+                if open.get(&path) == Some(&false) {
+                    open.remove(&path);
+                }
+                continue;
+            }
+            if open.lock().unwrap().contains_key(&path) {
+                continue;
+            }
+
+            if let Ok(dev) = Device::open(&path) {
+                spawn_handler(&tx, &open, &config, path, dev);
+            }
+        }
+    }
+}
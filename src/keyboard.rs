@@ -0,0 +1,79 @@
+use anyhow::Result;
+use anyhow::Context;
+
+use evdev::InputId;
+use evdev::BusType;
+use evdev::Key;
+use evdev::uinput::VirtualDevice;
+use evdev::uinput::VirtualDeviceBuilder;
+
+use crate::RINPUTER_VERSION;
+use crate::buttons::Buttons;
+use crate::registry::DeviceId;
+
+/// Keyboard keys, as opposed to BTN_* codes that belong to gamepads, mice and the like
+pub fn is_key(key: Key) -> bool {
+    let code = key.code();
+    (Key::KEY_ESC.code()..Key::BTN_0.code()).contains(&code)
+        || ((Key::KEY_OK.code()..Key::BTN_TRIGGER_HAPPY1.code()).contains(&code)
+            && !(Key::BTN_DPAD_UP.code()..=Key::BTN_DPAD_RIGHT.code()).contains(&code))
+}
+
+fn create_keyboard() -> Result<VirtualDevice> {
+    let mut keys = evdev::AttributeSet::<Key>::new();
+    for code in 0..Key::BTN_TRIGGER_HAPPY1.code() {
+        if is_key(Key::new(code)) {
+            keys.insert(Key::new(code));
+        }
+    }
+
+    VirtualDeviceBuilder::new()
+        .context("Failed to create instance of evdev::VirtualDeviceBuilder")?
+        .name(b"Rinputer Keyboard")
+        .input_id(InputId::new(BusType::BUS_USB, 0x0000, 0x0000, RINPUTER_VERSION))
+        .with_keys(&keys)?
+        .build()
+        .context("Failed to create uinput keyboard")
+}
+
+/// Virtual keyboard for remaps onto keyboard keys, only created once something uses it
+#[derive(Default)]
+pub struct Keyboard {
+    dev: Option<VirtualDevice>,
+    keys: Buttons,
+}
+
+impl Keyboard {
+    pub fn new() -> Keyboard {
+        Keyboard {
+            dev: None,
+            keys: Buttons::new(),
+        }
+    }
+
+    fn dev(&mut self) -> Result<&mut VirtualDevice> {
+        if self.dev.is_none() {
+            println!("Creating virtual keyboard");
+            self.dev = Some(create_keyboard()?);
+        }
+        Ok(self.dev.as_mut().unwrap())
+    }
+
+    pub fn key(&mut self, id: DeviceId, key: Key, value: i32) -> Result<()> {
+        self.keys.set(id, key, value);
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> Result<()> {
+        if let Some(frame) = self.keys.take() {
+            self.dev()?.emit(&frame)?;
+        }
+        Ok(())
+    }
+
+    /// Lets go of keys held by `id`, or every key on None
+    pub fn release(&mut self, id: Option<DeviceId>) -> Result<()> {
+        self.keys.release(id);
+        self.flush()
+    }
+}
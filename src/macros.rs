@@ -0,0 +1,196 @@
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::time::Duration;
+use std::time::Instant;
+
+use serde::{Deserialize, Serialize};
+
+use evdev::AbsoluteAxisType;
+use evdev::EventType;
+use evdev::InputEvent;
+use evdev::Key;
+use evdev::Synchronization;
+
+use crate::InputRemap;
+use crate::registry::DeviceId;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum MacroStep {
+    Press(Key),
+    Release(Key),
+    /// Moves an axis to a value, 0 puts it back at rest
+    Abs(AbsoluteAxisType, i32),
+    /// Milliseconds to wait, steps between waits go out as a single frame
+    Wait(u64),
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MacroConfig {
+    pub steps: Vec<MacroStep>,
+    /// Stops the macro when what started it is let go, letting go of everything it held
+    #[serde(default)]
+    pub cancel_on_release: bool,
+}
+
+/// Name `InputRemap::SteamQuickAccess` runs under, can be overridden in the config
+const STEAM_QUICK_ACCESS: &str = "SteamQuickAccess";
+
+fn steam_quick_access() -> MacroConfig {
+    MacroConfig {
+        steps: vec![
+            MacroStep::Press(Key::BTN_MODE),
+            MacroStep::Wait(100),
+            MacroStep::Press(Key::BTN_SOUTH),
+            MacroStep::Wait(100),
+            MacroStep::Release(Key::BTN_SOUTH),
+            MacroStep::Release(Key::BTN_MODE),
+        ],
+        cancel_on_release: false,
+    }
+}
+
+struct Running {
+    config: MacroConfig,
+    // device and input that started it
+    trigger: (DeviceId, InputRemap),
+    slot: usize,
+    step: usize,
+    due: Instant,
+    cancelled: bool,
+    held_keys: Vec<Key>,
+    held_axes: Vec<AbsoluteAxisType>,
+}
+
+impl Running {
+    /// Runs steps up to the next wait, or lets go of everything when cancelled
+    fn advance(&mut self, now: Instant, out: &mut Vec<(usize, InputEvent)>) -> bool {
+        let mut frame = Vec::new();
+        if self.cancelled {
+            for key in self.held_keys.drain(..) {
+                frame.push(InputEvent::new(EventType::KEY, key.code(), 0));
+            }
+            for axis in self.held_axes.drain(..) {
+                frame.push(InputEvent::new(EventType::ABSOLUTE, axis.0, 0));
+            }
+            self.step = self.config.steps.len();
+        }
+
+        while let Some(step) = self.config.steps.get(self.step) {
+            self.step += 1;
+            match *step {
+                MacroStep::Press(key) => {
+                    self.held_keys.push(key);
+                    frame.push(InputEvent::new(EventType::KEY, key.code(), 1));
+                },
+                MacroStep::Release(key) => {
+                    self.held_keys.retain(|k| *k != key);
+                    frame.push(InputEvent::new(EventType::KEY, key.code(), 0));
+                },
+                MacroStep::Abs(axis, value) => {
+                    self.held_axes.retain(|a| *a != axis);
+                    if value != 0 {
+                        self.held_axes.push(axis);
+                    }
+                    frame.push(InputEvent::new(EventType::ABSOLUTE, axis.0, value));
+                },
+                MacroStep::Wait(ms) => {
+                    self.due = now + Duration::from_millis(ms);
+                    break;
+                },
+            }
+        }
+
+        if !frame.is_empty() {
+            frame.push(InputEvent::new(EventType::SYNCHRONIZATION, Synchronization::SYN_REPORT.0, 0));
+            out.extend(frame.into_iter().map(|ev| (self.slot, ev)));
+        }
+        self.step < self.config.steps.len()
+    }
+}
+
+/// Runs macros off the main loop's timer, instead of a thread each
+///
+/// A macro starts when its input goes active and not again until that input has been let go,
+/// so an axis past its threshold doesn't start a new one with every event.
+pub struct Macros {
+    macros: HashMap<String, MacroConfig>,
+    running: Vec<Running>,
+    // inputs currently past their threshold
+    active: HashSet<(DeviceId, InputRemap)>,
+}
+
+impl Macros {
+    pub fn new(config: &[(String, MacroConfig)]) -> Macros {
+        let mut macros: HashMap<String, MacroConfig> = config.iter().cloned().collect();
+        macros.entry(STEAM_QUICK_ACCESS.to_string()).or_insert_with(steam_quick_access);
+        Macros {
+            macros,
+            running: Vec::new(),
+            active: HashSet::new(),
+        }
+    }
+
+    /// When the next step is due
+    pub fn deadline(&self) -> Option<Instant> {
+        self.running.iter().map(|r| r.due).min()
+    }
+
+    /// Feeds the state of an input remapped onto `target`, from device `id` driving player `slot`
+    pub fn trigger(&mut self, id: DeviceId, slot: usize, input: InputRemap, target: &InputRemap, active: bool) {
+        let name = match target {
+            InputRemap::Macro(name) => name.as_str(),
+            InputRemap::SteamQuickAccess => STEAM_QUICK_ACCESS,
+            _ => return,
+        };
+        let trigger = (id, input);
+
+        if !active {
+            if self.active.remove(&trigger) {
+                for running in self.running.iter_mut().filter(|r| r.trigger == trigger && r.config.cancel_on_release) {
+                    running.cancelled = true;
+                    running.due = Instant::now();
+                }
+            }
+            return;
+        }
+        if self.active.contains(&trigger) {
+            return;
+        }
+
+        let config = match self.macros.get(name) {
+            Some(config) => config.clone(),
+            None => {
+                eprintln!("No macro called {}", name);
+                return;
+            },
+        };
+        self.active.insert(trigger.clone());
+        self.running.push(Running {
+            config,
+            trigger,
+            slot,
+            step: 0,
+            due: Instant::now(),
+            cancelled: false,
+            held_keys: Vec::new(),
+            held_axes: Vec::new(),
+        });
+    }
+
+    /// Cancels what `id` started, as if it let go of everything
+    pub fn disconnect(&mut self, id: DeviceId) {
+        self.active.retain(|(owner, _)| *owner != id);
+        for running in self.running.iter_mut().filter(|r| r.trigger.0 == id && r.config.cancel_on_release) {
+            running.cancelled = true;
+            running.due = Instant::now();
+        }
+    }
+
+    /// Steps that are due, with the player slot each goes to, every frame ends with a SYN_REPORT
+    pub fn run(&mut self) -> Vec<(usize, InputEvent)> {
+        let now = Instant::now();
+        let mut out = Vec::new();
+        self.running.retain_mut(|r| r.due > now || r.advance(now, &mut out));
+        out
+    }
+}
@@ -1,8 +1,9 @@
 use std::thread;
-use std::time::Duration;
+use std::time::Instant;
 use std::str::FromStr;
 use std::sync::mpsc;
 use std::sync::mpsc::Sender;
+use std::sync::mpsc::RecvTimeoutError;
 use std::collections::HashMap;
 use std::collections::HashSet;
 use std::fs::File;
@@ -13,23 +14,85 @@ use std::io::Write;
 use std::hash::{Hasher, Hash};
 use std::path::Path;
 use std::path::PathBuf;
-use std::num::Wrapping;
 
 use serde::{Deserialize, Serialize};
 use clap::Parser;
 use anyhow::Result;
 use anyhow::Context;
 use interprocess::os::unix::fifo_file;
+use nix::sys::signal::{SigSet, Signal};
 
 use evdev::Device;
 use evdev::InputEvent;
 use evdev::Key;
 use evdev::AbsoluteAxisType;
+use evdev::RelativeAxisType;
 use evdev::InputEventKind;
+use evdev::Synchronization;
 use evdev::InputId;
-use evdev::UinputAbsSetup;
-use evdev::AbsInfo;
+use evdev::FFEffect;
+use evdev::FFEffectType;
 use evdev::uinput::VirtualDeviceBuilder;
+use evdev::uinput::VirtualDevice;
+
+mod hotplug;
+mod registry;
+mod filter;
+mod calibration;
+mod deadzone;
+mod curve;
+mod transform;
+mod players;
+mod mixer;
+mod profile;
+mod rumble;
+mod mouse;
+mod keyboard;
+mod buttons;
+mod mode;
+mod motion;
+mod gyro;
+mod flick;
+mod chord;
+mod macros;
+
+use registry::DeviceId;
+use registry::DeviceInfo;
+use registry::Registry;
+use filter::FilterRule;
+use filter::DeviceMatch;
+use deadzone::StickDeadzones;
+use deadzone::Sticks;
+use curve::ResponseCurve;
+use transform::AxisTransform;
+use transform::Transforms;
+use players::Players;
+use mixer::Mixer;
+use mixer::MergeConfig;
+use profile::OutputProfile;
+use rumble::Rumble;
+use rumble::RumbleEffect;
+use mouse::Mouse;
+use mouse::MouseConfig;
+use keyboard::Keyboard;
+use mode::Mode;
+use mode::ModeConfig;
+use mode::ModeSwitch;
+use motion::MotionFrame;
+use motion::MountMatrix;
+use gyro::Gyro;
+use gyro::GyroConfig;
+use gyro::GyroEvent;
+use flick::FlickStick;
+use flick::FlickConfig;
+use chord::Chords;
+use chord::ChordConfig;
+use macros::Macros;
+use macros::MacroConfig;
+use calibration::Calibration;
+
+/// Version every device rinputer creates has, so that none of them get picked up as input again
+const RINPUTER_VERSION: u16 = 0x2137;
 
 static MAX_OUT_ANALOG: i32 = 32767;
 static MIN_OUT_ANALOG: i32 = -32768;
@@ -49,48 +112,77 @@ struct Cli {
     enable_ipc: bool,
     #[clap(long, short, value_parser)]
     config: Option<PathBuf>,
+    /// Where to look for /sys and /proc when matching DMI and device-tree profiles
+    #[clap(long, value_parser, default_value = "/")]
+    sysfs_root: PathBuf,
 }
 
 #[inline]
 fn remap(x: i32, min: i32, max: i32, outmin: i32, outmax: i32) -> i32 {
-    (Wrapping(x - min) * Wrapping(outmax - outmin) / Wrapping(max - min) + Wrapping(outmin)).0
+    // i64 so that full 16-bit ranges on both sides don't overflow
+    ((x as i64 - min as i64) * (outmax as i64 - outmin as i64) / (max as i64 - min as i64) + outmin as i64) as i32
 }
 
 #[inline]
-fn has_key(dev: &Device, key: evdev::Key) -> bool {
-    dev.supported_keys().map_or(false, |keys| keys.contains(key))
+fn is_hat(axis: AbsoluteAxisType) -> bool {
+    (AbsoluteAxisType::ABS_HAT0X.0..=AbsoluteAxisType::ABS_HAT3Y.0).contains(&axis.0)
 }
 
-fn input_handler(tx: Sender<RinputerEvent>, mut dev: Device) -> Result<()> {
-    let mut useful = false;
+fn output_range(axis: AbsoluteAxisType) -> (i32, i32) {
+    match axis {
+        AbsoluteAxisType::ABS_Z     => (MIN_OUT_TRIG, MAX_OUT_TRIG),
+        AbsoluteAxisType::ABS_RZ    => (MIN_OUT_TRIG, MAX_OUT_TRIG),
+        a if is_hat(a)              => (MIN_OUT_HAT, MAX_OUT_HAT),
+        _ => (MIN_OUT_ANALOG, MAX_OUT_ANALOG),
+    }
+}
 
-    // gamepads
-    if has_key(&dev, Key::BTN_SOUTH) {
-        useful = true;
+fn apply_curve(curves: &HashMap<u16, ResponseCurve>, ev: InputEvent) -> InputEvent {
+    match ev.kind() {
+        InputEventKind::AbsAxis(a) => match curves.get(&a.0) {
+            Some(curve) => InputEvent::new(ev.event_type(), ev.code(), curve.apply(a, ev.value())),
+            None => ev,
+        },
+        _ => ev,
     }
+}
 
-    // extra buttons on x86 handhelds
-    // TODO: use config values for this
-    if has_key(&dev, Key::KEY_LEFTMETA) && dev.input_id().bus_type() == evdev::BusType::BUS_I8042 {
-        useful = true;
+/// Mirrors a keyboard, so that keys rinputer doesn't use still reach the system while it's grabbed
+fn create_passthrough(dev: &Device) -> Result<VirtualDevice> {
+    let id = dev.input_id();
+    let mut builder = VirtualDeviceBuilder::new()
+        .context("Failed to create instance of evdev::VirtualDeviceBuilder")?
+        .name(dev.name().unwrap_or("Keyboard").as_bytes())
+        .input_id(InputId::new(id.bus_type(), id.vendor(), id.product(), RINPUTER_VERSION));
+    if let Some(keys) = dev.supported_keys() {
+        builder = builder.with_keys(keys)?;
     }
+    builder.build().context("Failed to create keyboard passthrough device")
+}
 
-    // touchscreens
-    if has_key(&dev, Key::BTN_TOUCH) {
-        useful = false;
+fn input_handler(tx: Sender<RinputerEvent>, id: DeviceId, path: &Path, mut dev: Device, config: &HandlerConfig) -> Result<()> {
+    // our own devices are never picked up, whatever the filter says, or they'd feed back into us
+    if dev.input_id().version() == RINPUTER_VERSION {
+        return Ok(());
     }
 
-    // rinputer
-    if dev.input_id().version() == 0x2137 {
-        useful = false;
+    // sensors never look like gamepads, so they don't need an Include, but Exclude rules still apply
+    if motion::is_motion(&dev) {
+        return if config.motion && !filter::is_excluded(&config.device_filter, &dev) {
+            motion::motion_handler(tx, id, path, dev, config.imu_mount_matrix)
+        } else {
+            Ok(())
+        };
     }
 
-    // steam input, note the space
-    if dev.name().unwrap_or("Microsoft X-Box 360 pad ").starts_with("Microsoft X-Box 360 pad ") {
-        useful = false;
+    if !filter::is_useful(&config.device_filter, &dev) {
+        return Ok(());
     }
 
-    if !useful {
+    // built-in keyboards are only taken over when the machine profile asks for it,
+    // and then only the keys it uses are kept, the rest goes back to the system
+    let is_i8042 = dev.input_id().bus_type() == evdev::BusType::BUS_I8042;
+    if is_i8042 && !config.enable_i8042 {
         return Ok(());
     }
 
@@ -98,48 +190,60 @@ fn input_handler(tx: Sender<RinputerEvent>, mut dev: Device) -> Result<()> {
         Ok(()) => println!("Device {} deemed useful", dev.name().unwrap_or("<invalid name>")),
         Err(_) => return Ok(()), // fail silently in case someone else grabbed it before us
     }
-    
-    let (min_analog, max_analog, min_trig, max_trig) = if let Ok(absinfo) = dev.get_abs_state() {
-        (absinfo[AbsoluteAxisType::ABS_X.0 as usize].minimum,
-        absinfo[AbsoluteAxisType::ABS_Y.0 as usize].maximum,
-        absinfo[AbsoluteAxisType::ABS_Z.0 as usize].minimum,
-        absinfo[AbsoluteAxisType::ABS_RZ.0 as usize].maximum)
-    } else {
-        (0, 0, 0, 0)
-    };
+    let _connection = hotplug::connect(&tx, id, DeviceInfo::new(path, &dev, true))?;
+
+    if dev.supported_ff().map_or(false, |ff| ff.contains(FFEffectType::FF_RUMBLE)) {
+        // uploaded through the grabbing fd, effects from anywhere else don't reach a grabbed device
+        let stopped = RumbleEffect { strong: 0, weak: 0, length: 0 };
+        match dev.upload_ff_effect(stopped.data()) {
+            Ok(effect) => tx.send(RinputerEvent::RumbleConnected(id, effect))?,
+            Err(e) => eprintln!("Failed to set up rumble on {}: {}", dev.name().unwrap_or("<invalid name>"), e),
+        }
+    }
 
-    let skip_remap_analog = min_analog == MIN_OUT_ANALOG && max_analog == MAX_OUT_ANALOG;
+    let mut passthrough = if is_i8042 { Some(create_passthrough(&dev)?) } else { None };
+    let mut passthrough_frame = Vec::new();
+
+    let calibration = Calibration::new(&dev);
+    let mut transforms = Transforms::new(config.transforms_for(&dev));
+    let mut sticks = Sticks::new(config.deadzones_for(&dev));
 
     loop {
+        // on SYN_DROPPED this makes up events that bring state back in line, followed by a SYN_REPORT
         for ev in dev.fetch_events()? {
             match ev.kind() {
                 InputEventKind::AbsAxis(t) => {
-                    let val = match t {
-                        AbsoluteAxisType::ABS_HAT0Y => ev.value(), // assuming it's always between -1
-                        AbsoluteAxisType::ABS_HAT0X => ev.value(), // and 1
-                        AbsoluteAxisType::ABS_Z     => remap(ev.value(), min_trig, max_trig, MIN_OUT_TRIG, MAX_OUT_TRIG),
-                        AbsoluteAxisType::ABS_RZ    => remap(ev.value(), min_trig, max_trig, MIN_OUT_TRIG, MAX_OUT_TRIG),
-                        _ => if skip_remap_analog { ev.value() } else {remap(ev.value(), min_analog, max_analog, MIN_OUT_ANALOG, MAX_OUT_ANALOG) },
-                    };
-                    tx.send(RinputerEvent::InputEvent(InputEvent::new(ev.event_type(), ev.code(), val)))?;
+                    let val = calibration.normalize(t, ev.value());
+                    for (axis, val) in transforms.process(t, val) {
+                        if let Some(filtered) = sticks.process(axis, val) {
+                            for (axis, v) in filtered {
+                                tx.send(RinputerEvent::InputEvent(id, InputEvent::new(ev.event_type(), axis.0, v)))?;
+                            }
+                        } else {
+                            tx.send(RinputerEvent::InputEvent(id, InputEvent::new(ev.event_type(), axis.0, val)))?;
+                        }
+                    }
+                },
+                InputEventKind::Key(k) => match passthrough {
+                    Some(_) if !config.i8042_keys.contains(&k) => passthrough_frame.push(ev),
+                    _ => tx.send(RinputerEvent::InputEvent(id, ev))?,
+                },
+                InputEventKind::Synchronization(Synchronization::SYN_REPORT) => {
+                    if let Some(ref mut pt) = passthrough {
+                        if !passthrough_frame.is_empty() {
+                            pt.emit(&passthrough_frame)?;
+                            passthrough_frame.clear();
+                        }
+                    }
+                    // end of frame, main loop sends out everything since the last one at once
+                    tx.send(RinputerEvent::InputEvent(id, ev))?;
                 },
-                InputEventKind::Key(_) => tx.send(RinputerEvent::InputEvent(ev))?,
                 _ => (),
             }
         }
     }
 }
 
-fn indev_watcher(tx: Sender<RinputerEvent>) {
-    loop {
-        for device in evdev::enumerate() {
-            let new_tx = tx.clone();
-            thread::spawn(move || input_handler(new_tx, device.1));
-        }
-        thread::sleep(Duration::from_secs(1));
-    }
-}
-
 fn reader_ipc(tx: Sender<RinputerEvent>) -> Result<()> {
     loop {
         let reader = BufReader::new(File::open("/var/run/rinputer.sock")?);
@@ -161,19 +265,59 @@ fn reader_ipc(tx: Sender<RinputerEvent>) -> Result<()> {
             } else if line.starts_with("reset") {
                 tx.send(RinputerEvent::ResetConfig)?;
             } else if line.starts_with("print") {
+            } else if line.starts_with("devices") {
+                tx.send(RinputerEvent::PrintDevices)?;
+            } else if let Some(mode) = line.strip_prefix("mode ") {
+                if mode.trim() == "toggle" {
+                    tx.send(RinputerEvent::SetMode(None))?;
+                } else if let Ok(mode) = Mode::from_str(mode) {
+                    tx.send(RinputerEvent::SetMode(Some(mode)))?;
+                }
             }
         }
     }
 }
 
-fn get_dmi(name: &str) -> String {
-    let path = format!("/sys/class/dmi/id/{}", name);
+fn signal_watcher(tx: Sender<RinputerEvent>, signals: SigSet) -> Result<()> {
+    let sig = signals.wait()?;
+    tx.send(RinputerEvent::Shutdown(sig))?;
+    Ok(())
+}
+
+fn get_dmi(root: &Path, name: &str) -> String {
+    let path = root.join("sys/class/dmi/id").join(name);
     match std::fs::read_to_string(&path) {
         Ok(s) => s.lines().next().unwrap_or("<failed to read>").to_string(),
         Err(_) => "<failed to read>".to_string()
     }
 }
 
+struct DmiIds {
+    product_name: String,
+    product_vendor: String,
+    board_name: String,
+    board_vendor: String,
+}
+
+impl DmiIds {
+    fn read(root: &Path) -> DmiIds {
+        DmiIds {
+            product_name: get_dmi(root, "product_name"),
+            product_vendor: get_dmi(root, "product_vendor"),
+            board_name: get_dmi(root, "board_name"),
+            board_vendor: get_dmi(root, "board_vendor"),
+        }
+    }
+
+    fn matches(&self, dev: &DmiStrings) -> bool {
+        let pn_match = match_str(&dev.product_name, &self.product_name, dev.relaxed_name);
+        let pv_match = match_str(&dev.product_vendor, &self.product_vendor, dev.relaxed_vendor);
+        let bn_match = match_str(&dev.board_name, &self.board_name, dev.relaxed_name);
+        let bv_match = match_str(&dev.board_vendor, &self.board_vendor, dev.relaxed_vendor);
+        pn_match && pv_match && bn_match && bv_match
+    }
+}
+
 fn match_str(inp: &Option<String>, x: &str, relaxed: bool) -> bool {
     if let Some(template) = inp {
         if relaxed {
@@ -186,59 +330,129 @@ fn match_str(inp: &Option<String>, x: &str, relaxed: bool) -> bool {
     }
 }
 
-fn configure(tx: Sender<RinputerEvent>, path: PathBuf) {
+fn load_config(path: &Path) -> Result<RinputerConfig> {
     println!("Loading config file");
-    let f = File::open(&path)
-        .with_context(|| format!("Failed opening config file {}", path.display())).unwrap();
-    let config: RinputerConfig = ron::de::from_reader(f)
-        .with_context(|| format!("Config file {} has errors", path.display())).unwrap();
-
-    #[cfg(any(target_arch = "x86_64", target_arch = "x86"))]
-    {
-        println!("Detected x86 device, using DMI IDs");
-        let product_name = get_dmi("product_name");
-        let product_vendor = get_dmi("product_vendor");
-        let board_name = get_dmi("board_name");
-        let board_vendor = get_dmi("board_vendor");
-
-        for dev in config.dmi_strings {
-            let pn_match = match_str(&dev.product_name, &product_name, dev.relaxed_name);
-            let pv_match = match_str(&dev.product_vendor, &product_vendor, dev.relaxed_vendor);
-            let bn_match = match_str(&dev.board_name, &board_name, dev.relaxed_name);
-            let bv_match = match_str(&dev.board_vendor, &board_vendor, dev.relaxed_vendor);
-            if pn_match && pv_match && bn_match && bv_match {
-                println!("Found device match by DMI: {}", dev.display_name);
-                for map in dev.remap {
-                    println!("Applying remap: {:?}", map);
-                    tx.send(RinputerEvent::ConfigUpdate(map.0, map.1)).unwrap();
-                }
-                break;
-            }
+    let f = File::open(path)
+        .with_context(|| format!("Failed opening config file {}", path.display()))?;
+    let config = ron::de::from_reader(f)
+        .with_context(|| format!("Config file {} has errors", path.display()))?;
+    Ok(config)
+}
+
+struct DtIds {
+    compatible: Vec<String>,
+    model: String,
+}
+
+impl DtIds {
+    fn read(root: &Path) -> DtIds {
+        let dt = root.join("proc/device-tree");
+        // both are NUL-terminated, compatible is a list of NUL-separated strings
+        let compatible = std::fs::read_to_string(dt.join("compatible")).unwrap_or_default()
+            .split('\0')
+            .filter(|c| !c.is_empty())
+            .map(|c| c.to_string())
+            .collect();
+        let model = std::fs::read_to_string(dt.join("model")).unwrap_or_default()
+            .trim_end_matches('\0')
+            .to_string();
+        DtIds { compatible, model }
+    }
+
+    fn matches(&self, dev: &DtStrings) -> bool {
+        self.compatible.contains(&dev.compatible)
+            && dev.model.as_ref().map_or(true, |m| self.model.contains(m.as_str()))
+    }
+}
+
+/// Settings coming from the DMI or device-tree entry that matches this machine
+#[derive(Debug, Default)]
+struct MachineProfile {
+    remap: Vec<(InputRemap, InputRemap)>,
+    deadzones: Option<StickDeadzones>,
+    enable_i8042: bool,
+    i8042_keys: Vec<Key>,
+    imu_mount_matrix: Option<MountMatrix>,
+}
+
+fn machine_profile(config: &RinputerConfig, root: &Path) -> MachineProfile {
+    if root.join("sys/class/dmi/id").exists() {
+        println!("Found DMI IDs, matching DMI profiles");
+        let ids = DmiIds::read(root);
+
+        if let Some(dev) = config.dmi_strings.iter().find(|dev| ids.matches(dev)) {
+            println!("Found device match by DMI: {}", dev.display_name);
+            let i8042_keys = dev.i8042_keys.clone().unwrap_or_else(|| {
+                dev.remap.iter()
+                    .filter_map(|(from, _)| if let InputRemap::Key(k) = from { Some(*k) } else { None })
+                    .chain(config.chords.rules.iter().flat_map(|(keys, _)| keys.iter().copied()))
+                    .collect()
+            });
+            return MachineProfile {
+                remap: dev.remap.clone(),
+                deadzones: dev.deadzones.clone(),
+                enable_i8042: dev.enable_i8042,
+                i8042_keys,
+                imu_mount_matrix: dev.imu_mount_matrix,
+            };
         }
     }
-    #[cfg(any(target_arch = "arm", target_arch = "aarch64"))]
-    {
-        todo!("ARM Compatibles");
-        println!("Detected ARM device, using DT compatibles");
+    // boards that have DMI tables but no entry of their own can still match by device-tree
+    if root.join("proc/device-tree").exists() {
+        let ids = DtIds::read(root);
+        println!("Found device-tree of {}, matching DT compatibles", ids.model);
+
+        if let Some(dev) = config.dt_strings.iter().find(|dev| ids.matches(dev)) {
+            println!("Found device match by DT: {}", dev.display_name);
+            return MachineProfile {
+                remap: dev.remap.clone(),
+                deadzones: dev.deadzones.clone(),
+                imu_mount_matrix: dev.imu_mount_matrix,
+                ..Default::default()
+            };
+        }
     }
+    MachineProfile::default()
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+fn configure(tx: Sender<RinputerEvent>, profile: &MachineProfile) -> Result<()> {
+    for map in &profile.remap {
+        println!("Applying remap: {:?}", map);
+        tx.send(RinputerEvent::ConfigUpdate(map.0.clone(), map.1.clone()))?;
+    }
+    Ok(())
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(tag = "type", content = "data")]
 enum InputRemap {
     Key(Key),
     Abs(AbsoluteAxisType, i32),
+    /// Whole axis, both directions
+    Axis(AbsoluteAxisType),
+    /// Virtual mouse axis, driven by an `Axis`
+    Rel(RelativeAxisType),
     SteamQuickAccess,
+    /// Macro from the config, by name
+    Macro(String),
+    /// Switches between gamepad and desktop mode on press
+    ToggleMode,
 }
 
 impl FromStr for InputRemap {
     type Err = ();
     fn from_str(input: &str) -> Result<InputRemap, ()> {
-        if let Ok(k) = Key::from_str(input) {
+        if let Some(name) = input.strip_prefix("Macro:") {
+            return Ok(InputRemap::Macro(name.to_string()));
+        } else if let Ok(k) = Key::from_str(input) {
             return Ok(InputRemap::Key(k));
+        } else if let Ok(r) = RelativeAxisType::from_str(input) {
+            return Ok(InputRemap::Rel(r));
         } else if input.contains("ABS") || input.contains("HAT") {
             let split: Vec<&str> = input.split("@").collect();
-            if split.len() != 2 {
+            if split.len() == 1 {
+                return AbsoluteAxisType::from_str(split[0]).map(InputRemap::Axis).map_err(|_| ());
+            } else if split.len() != 2 {
                 return Err(())
             }
             if let Ok(a) = AbsoluteAxisType::from_str(split[0]) {
@@ -249,6 +463,8 @@ impl FromStr for InputRemap {
             return Err(())
         } else if input.contains("SteamQuickAccess") {
             return Ok(InputRemap::SteamQuickAccess);
+        } else if input == "ToggleMode" {
+            return Ok(InputRemap::ToggleMode);
         }
         Err(())
     }
@@ -274,6 +490,19 @@ impl Hash for InputRemap {
                 state.write_u16(a.0);
             },
             InputRemap::SteamQuickAccess => state.write_u8(6),
+            InputRemap::Axis(a) => {
+                state.write_u8(7);
+                state.write_u16(a.0);
+            },
+            InputRemap::Rel(r) => {
+                state.write_u8(8);
+                state.write_u16(r.0);
+            },
+            InputRemap::Macro(name) => {
+                state.write_u8(9);
+                name.hash(state);
+            },
+            InputRemap::ToggleMode => state.write_u8(10),
         }
     }
 }
@@ -293,19 +522,35 @@ impl PartialEq for InputRemap {
                     false
                 },
             InputRemap::SteamQuickAccess => other == &InputRemap::SteamQuickAccess,
+            InputRemap::Axis(a) => matches!(other, InputRemap::Axis(b) if a == b),
+            InputRemap::Rel(a) => matches!(other, InputRemap::Rel(b) if a == b),
+            InputRemap::Macro(a) => matches!(other, InputRemap::Macro(b) if a == b),
+            InputRemap::ToggleMode => other == &InputRemap::ToggleMode,
         }
     }
 }
 impl Eq for InputRemap {}
 
 enum RinputerEvent {
-    InputEvent(InputEvent),
+    InputEvent(DeviceId, InputEvent),
+    DeviceConnected(DeviceId, DeviceInfo),
+    DeviceDisconnected(DeviceId),
+    RumbleConnected(DeviceId, FFEffect),
+    Motion(DeviceId, MotionFrame),
+    Rumble(usize, Option<RumbleEffect>),
     ConfigUpdate(InputRemap, InputRemap),
     PrintConfig,
+    PrintDevices,
     ResetConfig,
+    /// None switches to the other mode
+    SetMode(Option<Mode>),
+    /// A chord window ran out or a macro step is due, never sent over the channel
+    Timer,
+    Shutdown(Signal),
 }
 
 fn bool_false() -> bool {false}
+fn bool_true() -> bool {true}
 
 #[derive(Debug, Serialize, Deserialize)]
 struct DmiStrings {
@@ -316,18 +561,31 @@ struct DmiStrings {
     product_name: Option<String>,
     #[serde(default = "bool_false")]
     enable_i8042: bool,
+    /// Built-in keyboard keys rinputer keeps, defaults to every key used in `remap`
+    #[serde(default)]
+    i8042_keys: Option<Vec<Key>>,
     #[serde(default = "bool_false")]
     relaxed_name: bool,
     #[serde(default = "bool_false")]
     relaxed_vendor: bool,
     remap: Vec<(InputRemap, InputRemap)>,
+    #[serde(default)]
+    deadzones: Option<StickDeadzones>,
+    #[serde(default)]
+    imu_mount_matrix: Option<MountMatrix>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 struct DtStrings {
     display_name: String,
     compatible: String,
+    #[serde(default)]
+    model: Option<String>,
     remap: Vec<(InputRemap, InputRemap)>,
+    #[serde(default)]
+    deadzones: Option<StickDeadzones>,
+    #[serde(default)]
+    imu_mount_matrix: Option<MountMatrix>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -337,86 +595,202 @@ struct RinputerConfig {
     dmi_strings: Vec<DmiStrings>,
     #[serde(rename = "dt_device")]
     dt_strings: Vec<DtStrings>,
+    #[serde(default = "filter::default_rules")]
+    device_filter: Vec<FilterRule>,
+    #[serde(default)]
+    deadzones: StickDeadzones,
+    #[serde(default)]
+    device_deadzones: Vec<(DeviceMatch, StickDeadzones)>,
+    #[serde(default)]
+    curves: Vec<(AbsoluteAxisType, ResponseCurve)>,
+    #[serde(default)]
+    device_transforms: Vec<(DeviceMatch, Vec<AxisTransform>)>,
+    /// Turns a stick into a flick stick, off when left out
+    #[serde(default)]
+    flick_stick: Option<FlickConfig>,
+    /// Separate virtual pad for every controller
+    #[serde(default = "bool_false")]
+    multiplayer: bool,
+    #[serde(default)]
+    merge: MergeConfig,
+    #[serde(default)]
+    output_profile: OutputProfile,
+    /// Buttons on top of the ones the profile always has, like back paddles, defaults to the profile's own
+    #[serde(default)]
+    extra_buttons: Option<Vec<Key>>,
+    /// Rumble strength per device, first match wins, 1.0 for everything else
+    #[serde(default)]
+    rumble: Vec<(DeviceMatch, f32)>,
+    #[serde(default)]
+    mouse: MouseConfig,
+    #[serde(default)]
+    mode: ModeConfig,
+    /// Used instead of the regular remaps in desktop mode
+    #[serde(default = "default_desktop_remap")]
+    desktop_remap: Vec<(InputRemap, InputRemap)>,
+    /// Pass motion sensors through as part of the pad
+    #[serde(default = "bool_true")]
+    motion: bool,
+    /// Aiming with motion sensors, off when left out
+    #[serde(default)]
+    gyro: Option<GyroConfig>,
+    /// Buttons held together that act as a single one
+    #[serde(default)]
+    chords: ChordConfig,
+    /// Named macros, for `Macro` remaps
+    #[serde(default)]
+    macros: Vec<(String, MacroConfig)>,
 }
 
-fn steam_quick_access(tx: Sender<RinputerEvent>) {
-    tx.send(RinputerEvent::InputEvent(InputEvent::new(evdev::EventType::KEY, Key::BTN_MODE.0, 1)));
-    thread::sleep(Duration::from_millis(100));
-    tx.send(RinputerEvent::InputEvent(InputEvent::new(evdev::EventType::KEY, Key::BTN_SOUTH.0, 1)));
-    thread::sleep(Duration::from_millis(100));
-    tx.send(RinputerEvent::InputEvent(InputEvent::new(evdev::EventType::KEY, Key::BTN_SOUTH.0, 0)));
-    tx.send(RinputerEvent::InputEvent(InputEvent::new(evdev::EventType::KEY, Key::BTN_MODE.0, 0)));
+fn default_desktop_remap() -> Vec<(InputRemap, InputRemap)> {
+    vec![
+        (InputRemap::Axis(AbsoluteAxisType::ABS_X),     InputRemap::Rel(RelativeAxisType::REL_X)),
+        (InputRemap::Axis(AbsoluteAxisType::ABS_Y),     InputRemap::Rel(RelativeAxisType::REL_Y)),
+        (InputRemap::Axis(AbsoluteAxisType::ABS_RX),    InputRemap::Rel(RelativeAxisType::REL_HWHEEL)),
+        (InputRemap::Axis(AbsoluteAxisType::ABS_RY),    InputRemap::Rel(RelativeAxisType::REL_WHEEL)),
+
+        (InputRemap::Key(Key::BTN_SOUTH),       InputRemap::Key(Key::BTN_LEFT)),
+        (InputRemap::Key(Key::BTN_EAST),        InputRemap::Key(Key::BTN_RIGHT)),
+        (InputRemap::Key(Key::BTN_TR),          InputRemap::Key(Key::BTN_LEFT)),
+        (InputRemap::Key(Key::BTN_TL),          InputRemap::Key(Key::BTN_RIGHT)),
+        (InputRemap::Key(Key::BTN_START),       InputRemap::Key(Key::KEY_ENTER)),
+        (InputRemap::Key(Key::BTN_SELECT),      InputRemap::Key(Key::KEY_ESC)),
+        (InputRemap::Key(Key::BTN_NORTH),       InputRemap::Key(Key::KEY_LEFTMETA)),
+
+        (InputRemap::Key(Key::BTN_DPAD_UP),     InputRemap::Key(Key::KEY_UP)),
+        (InputRemap::Key(Key::BTN_DPAD_DOWN),   InputRemap::Key(Key::KEY_DOWN)),
+        (InputRemap::Key(Key::BTN_DPAD_LEFT),   InputRemap::Key(Key::KEY_LEFT)),
+        (InputRemap::Key(Key::BTN_DPAD_RIGHT),  InputRemap::Key(Key::KEY_RIGHT)),
+    ]
+}
+
+impl Default for RinputerConfig {
+    fn default() -> RinputerConfig {
+        RinputerConfig {
+            global_remap: Vec::new(),
+            dmi_strings: Vec::new(),
+            dt_strings: Vec::new(),
+            device_filter: filter::default_rules(),
+            deadzones: StickDeadzones::default(),
+            device_deadzones: Vec::new(),
+            curves: Vec::new(),
+            device_transforms: Vec::new(),
+            flick_stick: None,
+            multiplayer: false,
+            merge: MergeConfig::default(),
+            output_profile: OutputProfile::default(),
+            extra_buttons: None,
+            rumble: Vec::new(),
+            mouse: MouseConfig::default(),
+            mode: ModeConfig::default(),
+            desktop_remap: default_desktop_remap(),
+            motion: true,
+            gyro: None,
+            chords: ChordConfig::default(),
+            macros: Vec::new(),
+        }
+    }
+}
+
+/// Parts of the config input handlers need, resolved once at startup
+#[derive(Debug)]
+struct HandlerConfig {
+    device_filter: Vec<FilterRule>,
+    deadzones: StickDeadzones,
+    device_deadzones: Vec<(DeviceMatch, StickDeadzones)>,
+    device_transforms: Vec<(DeviceMatch, Vec<AxisTransform>)>,
+    enable_i8042: bool,
+    i8042_keys: Vec<Key>,
+    motion: bool,
+    imu_mount_matrix: Option<MountMatrix>,
+}
+
+impl HandlerConfig {
+    fn new(config: &RinputerConfig, profile: &MachineProfile) -> HandlerConfig {
+        HandlerConfig {
+            enable_i8042: profile.enable_i8042,
+            i8042_keys: profile.i8042_keys.clone(),
+            device_filter: config.device_filter.clone(),
+            deadzones: profile.deadzones.clone().unwrap_or_else(|| config.deadzones.clone()),
+            device_deadzones: config.device_deadzones.clone(),
+            device_transforms: config.device_transforms.clone(),
+            motion: config.motion,
+            imu_mount_matrix: profile.imu_mount_matrix,
+        }
+    }
+
+    fn deadzones_for(&self, dev: &Device) -> &StickDeadzones {
+        self.device_deadzones.iter()
+            .find(|(m, _)| m.matches(dev))
+            .map_or(&self.deadzones, |(_, dz)| dz)
+    }
+
+    /// Whether any device gets a deadzone applied to its left and right stick
+    fn filtered_sticks(&self) -> [bool; 2] {
+        let all = || std::iter::once(&self.deadzones).chain(self.device_deadzones.iter().map(|(_, dz)| dz));
+        [all().any(|dz| dz.left.is_some()), all().any(|dz| dz.right.is_some())]
+    }
+
+    fn transforms_for(&self, dev: &Device) -> &[AxisTransform] {
+        self.device_transforms.iter()
+            .find(|(m, _)| m.matches(dev))
+            .map_or(&[], |(_, t)| t)
+    }
 }
 
 fn main() -> Result<()> {
     let args = Cli::parse();
-    let mut keys = evdev::AttributeSet::<Key>::new();
-    keys.insert(Key::BTN_SOUTH);
-    keys.insert(Key::BTN_EAST);
-    keys.insert(Key::BTN_NORTH);
-    keys.insert(Key::BTN_WEST);
-    keys.insert(Key::BTN_TL);
-    keys.insert(Key::BTN_TR);
-    keys.insert(Key::BTN_SELECT);
-    keys.insert(Key::BTN_START);
-    keys.insert(Key::BTN_MODE);
-    keys.insert(Key::BTN_THUMBL);
-    keys.insert(Key::BTN_THUMBR);
-
-    let input_id = InputId::new(evdev::BusType::BUS_USB, 0x045e, 0x028e, 0x2137);
-
-    let abs_analogs = AbsInfo::new(0, MIN_OUT_ANALOG, MAX_OUT_ANALOG, 16, 256, 0);
-    let abs_x = UinputAbsSetup::new(AbsoluteAxisType::ABS_X, abs_analogs);
-    let abs_y = UinputAbsSetup::new(AbsoluteAxisType::ABS_Y, abs_analogs);
-    let abs_rx = UinputAbsSetup::new(AbsoluteAxisType::ABS_RX, abs_analogs);
-    let abs_ry = UinputAbsSetup::new(AbsoluteAxisType::ABS_RY, abs_analogs);
-
-    let abs_triggers = AbsInfo::new(0, MIN_OUT_TRIG, MAX_OUT_TRIG, 0, 0, 0);
-    let abs_z = UinputAbsSetup::new(AbsoluteAxisType::ABS_Z, abs_triggers);
-    let abs_rz = UinputAbsSetup::new(AbsoluteAxisType::ABS_RZ, abs_triggers);
-
-    let abs_hat = AbsInfo::new(0, MIN_OUT_HAT, MAX_OUT_HAT, 0, 0, 0);
-    let abs_hat_x = UinputAbsSetup::new(AbsoluteAxisType::ABS_HAT0X, abs_hat);
-    let abs_hat_y = UinputAbsSetup::new(AbsoluteAxisType::ABS_HAT0Y, abs_hat);
-
-    let mut uhandle = VirtualDeviceBuilder::new()
-        .context("Failed to create instance of evdev::VirtualDeviceBuilder")?
-        .name(b"Microsoft X-Box 360 pad")
-        .input_id(input_id)
-        .with_keys(&keys)?
-        .with_absolute_axis(&abs_x)?
-        .with_absolute_axis(&abs_y)?
-        .with_absolute_axis(&abs_rx)?
-        .with_absolute_axis(&abs_ry)?
-        .with_absolute_axis(&abs_z)?
-        .with_absolute_axis(&abs_rz)?
-        .with_absolute_axis(&abs_hat_x)?
-        .with_absolute_axis(&abs_hat_y)?
-        .build()
-        .context("Failed to create uinput device")?;
+
+    // only signal_watcher gets to see these, every thread spawned after this inherits the mask
+    let mut signals = SigSet::empty();
+    signals.add(Signal::SIGINT);
+    signals.add(Signal::SIGTERM);
+    signals.thread_block().context("Failed to block signals")?;
 
     let (tx, rx) = mpsc::channel();
 
+    let tx5 = tx.clone();
+    thread::spawn(move || signal_watcher(tx5, signals));
+
     if args.enable_ipc {
         let tx2 = tx.clone();
         thread::spawn(move || reader_ipc(tx2));
     }
 
-    if let Some(conf) = args.config {
-        let tx3 = tx.clone();
-        thread::spawn(move || configure(tx3, conf));
+    let config = if let Some(conf) = args.config {
+        load_config(&conf)?
     } else {
         eprintln!("No config supplied!");
-    }
+        RinputerConfig::default()
+    };
+
+    let profile = machine_profile(&config, &args.sysfs_root);
+    let handler_config = HandlerConfig::new(&config, &profile);
+    configure(tx.clone(), &profile)?;
 
+    let curves: HashMap<u16, ResponseCurve> = config.curves.iter()
+        .map(|(a, c)| (a.0, c.clone()))
+        .collect();
+    let extra_buttons = config.extra_buttons.clone()
+        .unwrap_or_else(|| config.output_profile.default_extra_buttons());
+    let mut players = Players::new(config.multiplayer, config.output_profile, extra_buttons.clone(),
+        handler_config.filtered_sticks(), tx.clone())?;
+
+    let inotify = hotplug::watch_input()?;
     let tx4 = tx.clone();
-    thread::spawn(move || indev_watcher(tx4));
+    thread::spawn(move || {
+        if let Err(e) = hotplug::indev_watcher(inotify, tx4, handler_config) {
+            eprintln!("Device watcher stopped: {:#}", e);
+        }
+    });
 
+    let ipc_path = Path::new("/var/run/rinputer.sock");
+    let mut created_fifo = false;
     let mut output_ipc = if args.enable_ipc {
-        let ipc_path = Path::new("/var/run/rinputer.sock");
         if !ipc_path.exists() {
             fifo_file::create_fifo(ipc_path, 0o777)
                 .context("Failed creating fifo at /var/run/rinputer.sock")?;
+            created_fifo = true;
         }
         OpenOptions::new().read(false).append(true).create(false)
             .open("/var/run/rinputer.sock")
@@ -426,9 +800,7 @@ fn main() -> Result<()> {
             .open("/dev/null")?
     };
 
-    let allowed_keys: HashSet<evdev::Key> = HashSet::from([Key::BTN_SOUTH, Key::BTN_EAST, Key::BTN_NORTH,
-        Key::BTN_WEST, Key::BTN_TL, Key::BTN_TR, Key::BTN_SELECT, Key::BTN_START, Key::BTN_MODE, Key::BTN_THUMBL,
-        Key::BTN_THUMBR ]);
+    let allowed_keys: HashSet<evdev::Key> = config.output_profile.keys(&extra_buttons).into_iter().collect();
     let mut remaps = HashMap::from([
         (InputRemap::Key(Key::BTN_DPAD_UP),     InputRemap::Abs(AbsoluteAxisType::ABS_HAT0Y, -1)),
         (InputRemap::Key(Key::BTN_DPAD_DOWN),   InputRemap::Abs(AbsoluteAxisType::ABS_HAT0Y, 1)),
@@ -439,78 +811,236 @@ fn main() -> Result<()> {
         (InputRemap::Key(Key::BTN_TR2),         InputRemap::Abs(AbsoluteAxisType::ABS_RZ, 256)),
     ]);
 
+    let mut registry = Registry::new();
+    let mut mixer = Mixer::new(config.merge.clone());
+    let mut rumble = Rumble::new(config.rumble.clone());
+    let mut mouse = Mouse::new(config.mouse.clone());
+    let mut keyboard = Keyboard::new();
+    let mut switch = ModeSwitch::new(config.mode.clone());
+    let mut gyro = Gyro::new(config.gyro.clone());
+    let mut flick = FlickStick::new(config.flick_stick.clone());
+    let mut chords = Chords::new(config.chords.clone());
+    let mut macros = Macros::new(&config.macros);
+    let desktop_remaps: HashMap<InputRemap, InputRemap> = config.desktop_remap.iter().cloned().collect();
 
     // rinputer-event
-    for rev in rx {
+    loop {
+        let deadline = [chords.deadline(), macros.deadline()].into_iter().flatten().min();
+        let rev = match deadline {
+            Some(deadline) => match rx.recv_timeout(deadline.saturating_duration_since(Instant::now())) {
+                Ok(rev) => rev,
+                Err(RecvTimeoutError::Timeout) => RinputerEvent::Timer,
+                Err(RecvTimeoutError::Disconnected) => break,
+            },
+            None => match rx.recv() {
+                Ok(rev) => rev,
+                Err(_) => break,
+            },
+        };
+
+        // macro output skips the remap table, whatever it presses goes out as is
+        let desktop = switch.mode() == Mode::Desktop;
+        for (slot, ev) in macros.run() {
+            match ev.kind() {
+                InputEventKind::Synchronization(_) => {
+                    players.flush(DeviceId::INTERNAL)?;
+                    mouse.flush()?;
+                    keyboard.flush()?;
+                },
+                InputEventKind::Key(k) if mouse::is_button(k) => mouse.button(DeviceId::INTERNAL, k, ev.value())?,
+                InputEventKind::Key(k) if keyboard::is_key(k) => keyboard.key(DeviceId::INTERNAL, k, ev.value())?,
+                _ if desktop => (),
+                _ => {
+                    let ev = mixer.merge(slot, DeviceId::INTERNAL, ev, &registry);
+                    players.queue(DeviceId::INTERNAL, slot, apply_curve(&curves, ev));
+                },
+            }
+        }
+
         match rev {
-            RinputerEvent::InputEvent(ev) => {
-                match ev.kind() {
-                    InputEventKind::Key(mut k) => {
-                        if let Some(map) = remaps.get(&InputRemap::Key(k)) {
-                            match map {
-                                InputRemap::Key(new) => k = *new,
-                                InputRemap::SteamQuickAccess => { 
-                                    if ev.value() == 1 {
-                                        let tmp_tx = tx.clone();
-                                        thread::spawn(move || steam_quick_access(tmp_tx));
-                                    }
-                                    continue;
-                                },
-                                InputRemap::Abs(a, v) => {
-                                    let out = InputEvent::new(evdev::EventType::ABSOLUTE, a.0, v*ev.value());
-                                    uhandle.emit(&[out])?;
-                                    continue;
-                                },
+            RinputerEvent::Timer | RinputerEvent::InputEvent(..) => {
+                let (frame_id, events) = match rev {
+                    RinputerEvent::InputEvent(id, ev) => {
+                        if let InputEventKind::Synchronization(_) = ev.kind() {
+                            if switch.mode() == Mode::Gamepad {
+                                if let Some(dx) = flick.sync(id, players.slot(id)) {
+                                    mouse.move_by(dx, 0.0)?;
+                                }
                             }
+                            players.flush(id)?;
+                            mouse.flush()?;
+                            keyboard.flush()?;
+                            continue;
                         }
 
-                        if allowed_keys.contains(&k) {
-                            let out = InputEvent::new(ev.event_type(), k.code(), ev.value());
-                            uhandle.emit(&[out])?;
+                        registry.count_event(id);
+                        if let InputEventKind::Key(k) = ev.kind() {
+                            gyro.key(k, ev.value());
                         }
+                        (Some(id), chords.event(id, ev))
                     },
-                    InputEventKind::AbsAxis(a) => {
-                        if let Some((key, map)) = remaps.get_key_value(&InputRemap::Abs(a, ev.value())) {
-                            let out = match map {
-                                InputRemap::Key(k) => {
-                                    if let InputRemap::Abs(_, trig) = key {
-                                        if ev.value() == *trig {
-                                            InputEvent::new(evdev::EventType::KEY, k.0, 1)
-                                        } else if *trig > 0 {
-                                            InputEvent::new(evdev::EventType::KEY, k.0, if ev.value() > *trig {1} else {0})
-                                        } else {
-                                            InputEvent::new(evdev::EventType::KEY, k.0, if ev.value() < *trig {1} else {0})
+                    _ => (None, chords.expire()),
+                };
+                // held back presses can belong to other devices, no SYN_REPORT of theirs is coming for them
+                let late: HashSet<DeviceId> = events.iter()
+                    .map(|(id, _, _)| *id)
+                    .filter(|id| Some(*id) != frame_id)
+                    .collect();
+
+                let table = if desktop { &desktop_remaps } else { &remaps };
+                for (id, ev, chord) in events {
+                    let slot = players.slot(id);
+                    let mut emit = |out: InputEvent| -> Result<()> {
+                        if desktop {
+                            // the pad is left alone on the desktop
+                            return Ok(());
+                        }
+                        let out = mixer.merge(slot, id, out, &registry);
+                        players.queue(id, slot, apply_curve(&curves, out));
+                        Ok(())
+                    };
+                    match ev.kind() {
+                        InputEventKind::Key(mut k) => {
+                            let remapped = chord.as_ref().or_else(|| table.get(&InputRemap::Key(k)));
+                            if let Some(map) = remapped {
+                                match map {
+                                    InputRemap::Key(new) => k = *new,
+                                    InputRemap::SteamQuickAccess | InputRemap::Macro(_) => {
+                                        macros.trigger(id, slot, InputRemap::Key(k), map, ev.value() != 0);
+                                        continue;
+                                    },
+                                    InputRemap::Abs(a, v) => {
+                                        let out = InputEvent::new(evdev::EventType::ABSOLUTE, a.0, v*ev.value());
+                                        emit(out)?;
+                                        continue;
+                                    },
+                                    InputRemap::ToggleMode => {
+                                        if ev.value() == 1 {
+                                            tx.send(RinputerEvent::SetMode(None))?;
                                         }
-                                    } else { unreachable!() }
+                                        continue;
+                                    },
+                                    // need a whole axis to drive them
+                                    InputRemap::Axis(_) | InputRemap::Rel(_) => continue,
                                 }
-                                InputRemap::SteamQuickAccess => {
-                                    if let InputRemap::Abs(_, trig) = key {
-                                        if ev.value() > *trig {
-                                            let tmp_tx = tx.clone();
-                                            thread::spawn(move || steam_quick_access(tmp_tx));
-                                        } 
+                            }
+
+                            if allowed_keys.contains(&k) {
+                                let out = InputEvent::new(ev.event_type(), k.code(), ev.value());
+                                emit(out)?;
+                            } else if mouse::is_button(k) {
+                                mouse.button(id, k, ev.value())?;
+                            } else if remapped.is_some() && keyboard::is_key(k) {
+                                // unmapped keyboard keys are still dropped, only what the config asks for goes out
+                                keyboard.key(id, k, ev.value())?;
+                            }
+                        },
+                        InputEventKind::AbsAxis(a) if !desktop && flick.handles(a) => flick.axis(id, a, ev.value()),
+                        InputEventKind::AbsAxis(a) => {
+                            // macros see every value of the axis, the lookup below only finds entries on the
+                            // same side, so going back to rest or past the other side wouldn't let go of them
+                            for side in [1, -1, 0] {
+                                if let Some((key, map)) = table.get_key_value(&InputRemap::Abs(a, side)) {
+                                    if let (InputRemap::Abs(_, trig), InputRemap::SteamQuickAccess | InputRemap::Macro(_)) = (key, map) {
+                                        let active = ev.value() == *trig
+                                            || if *trig > 0 { ev.value() > *trig } else { ev.value() < *trig };
+                                        macros.trigger(id, slot, key.clone(), map, active);
                                     }
-                                    continue;
-                                },
-                                InputRemap::Abs(a, v) => {
-                                    let (min, max) = match *a {
-                                        AbsoluteAxisType::ABS_HAT0Y => (MIN_OUT_HAT, MAX_OUT_HAT),
-                                        AbsoluteAxisType::ABS_HAT0X => (MIN_OUT_HAT, MAX_OUT_HAT),
-                                        AbsoluteAxisType::ABS_Z     => (MIN_OUT_TRIG, MAX_OUT_TRIG),
-                                        AbsoluteAxisType::ABS_RZ    => (MIN_OUT_TRIG, MAX_OUT_TRIG),
-                                        _ => (MIN_OUT_ANALOG, MAX_OUT_ANALOG),
-                                    };
-                                    InputEvent::new(evdev::EventType::ABSOLUTE, a.0, remap(ev.value(), min, max, 0, *v))
-                                },
-                            };
-                            uhandle.emit(&[out])?;
-                        } else {
-                            uhandle.emit(&[ev])?;
+                                }
+                            }
+
+                            if let Some(InputRemap::Rel(r)) = table.get(&InputRemap::Axis(a)) {
+                                mouse.set_axis(id, *r, a, ev.value())?;
+                            } else if let Some((key, map)) = table.get_key_value(&InputRemap::Abs(a, ev.value())) {
+                                let out = match map {
+                                    InputRemap::Key(k) => {
+                                        if let InputRemap::Abs(_, trig) = key {
+                                            if ev.value() == *trig {
+                                                InputEvent::new(evdev::EventType::KEY, k.0, 1)
+                                            } else if *trig > 0 {
+                                                InputEvent::new(evdev::EventType::KEY, k.0, if ev.value() > *trig {1} else {0})
+                                            } else {
+                                                InputEvent::new(evdev::EventType::KEY, k.0, if ev.value() < *trig {1} else {0})
+                                            }
+                                        } else { unreachable!() }
+                                    }
+                                    // already fed to the macros above
+                                    InputRemap::SteamQuickAccess | InputRemap::Macro(_) => continue,
+                                    InputRemap::Abs(a, v) => {
+                                        let (min, max) = output_range(*a);
+                                        InputEvent::new(evdev::EventType::ABSOLUTE, a.0, remap(ev.value(), min, max, 0, *v))
+                                    },
+                                    // only buttons switch modes, an axis past its level would keep switching
+                                    InputRemap::Axis(_) | InputRemap::Rel(_) | InputRemap::ToggleMode => continue,
+                                };
+                                match out.kind() {
+                                    InputEventKind::Key(k) if mouse::is_button(k) => mouse.button(id, k, out.value())?,
+                                    InputEventKind::Key(k) if keyboard::is_key(k) => keyboard.key(id, k, out.value())?,
+                                    _ => emit(out)?,
+                                }
+                            } else {
+                                emit(ev)?;
+                            }
                         }
+                        _ => {},
+                    }
+                }
+                if !late.is_empty() {
+                    for id in late {
+                        players.flush(id)?;
+                    }
+                    mouse.flush()?;
+                    keyboard.flush()?;
+                }
+            },
+            RinputerEvent::DeviceConnected(id, info) => {
+                players.connect(id, &info, &registry)?;
+                registry.connect(id, info);
+            },
+            RinputerEvent::DeviceDisconnected(id) => {
+                // let go of everything it was holding
+                for (slot, ev) in mixer.release(id, &registry) {
+                    players.emit(slot, apply_curve(&curves, ev))?;
+                }
+                mouse.release(Some(id))?;
+                keyboard.release(Some(id))?;
+                rumble.disconnect(id);
+                gyro.disconnect(id);
+                flick.disconnect(id);
+                chords.disconnect(id);
+                macros.disconnect(id);
+                players.disconnect(id);
+                registry.disconnect(id);
+            },
+            RinputerEvent::Motion(id, frame) => {
+                registry.count_event(id);
+                let slot = players.slot(id);
+                // gyro aiming below sees the sensor as it is, without flicks
+                let mut turned = frame;
+                flick.motion(slot, &mut turned);
+                players.emit_motion(slot, &turned)?;
+
+                for out in gyro.process(id, &frame) {
+                    match out {
+                        GyroEvent::Mouse(dx, dy) => mouse.move_by(dx, dy)?,
+                        // goes through the mixer like any other device, so merge policies decide
+                        // how it gets along with the physical stick
+                        GyroEvent::Abs(axis, value) if switch.mode() == Mode::Gamepad => {
+                            let ev = InputEvent::new(evdev::EventType::ABSOLUTE, axis.0, value);
+                            let ev = mixer.merge(slot, id, ev, &registry);
+                            players.queue(id, slot, apply_curve(&curves, ev));
+                        },
+                        GyroEvent::Abs(..) => (),
                     }
-                    _ => {},
+                }
+                players.flush(id)?;
+            },
+            RinputerEvent::RumbleConnected(id, effect) => {
+                if let Some(info) = registry.get(id) {
+                    rumble.connect(id, effect, info);
                 }
             },
+            RinputerEvent::Rumble(slot, effect) => rumble.play(slot, effect, &players),
             RinputerEvent::ConfigUpdate(from, to) =>{
                 println!("Updating config, mapping {:?} into {:?}", from, to);
                 remaps.insert(from, to); // TODO: insert doesn't update key when changing abs
@@ -545,8 +1075,139 @@ fn main() -> Result<()> {
                 output_ipc.write(out.as_str().as_bytes())?;
                 output_ipc.flush()?;
             }
+            RinputerEvent::PrintDevices => {
+                output_ipc.write_all(b"Devices:\n")?;
+                for (id, info) in registry.iter() {
+                    output_ipc.write_all(format!("{}: player {}, {}\n", id, players.slot(*id) + 1, info).as_bytes())?;
+                }
+                output_ipc.flush()?;
+            }
+            RinputerEvent::SetMode(mode) => {
+                // nothing stays held across the switch
+                for (slot, ev) in mixer.neutral() {
+                    players.emit(slot, ev)?;
+                }
+                mouse.release(None)?;
+                keyboard.release(None)?;
+                if let Err(e) = switch.set(mode) {
+                    eprintln!("{:#}", e);
+                }
+            },
+            RinputerEvent::Shutdown(sig) => {
+                println!("Got {}, shutting down", sig);
+                if created_fifo {
+                    if let Err(e) = std::fs::remove_file(ipc_path) {
+                        eprintln!("Failed removing fifo at /var/run/rinputer.sock: {}", e);
+                    }
+                }
+                // best effort, nothing should stay held on the virtual devices, but failing here
+                // mustn't keep us from exiting
+                for (slot, ev) in mixer.neutral() {
+                    if let Err(e) = players.emit(slot, ev) {
+                        eprintln!("Failed releasing player {}: {}", slot + 1, e);
+                    }
+                }
+                if let Err(e) = mouse.release(None).and(keyboard.release(None)) {
+                    eprintln!("{:#}", e);
+                }
+                // grabs go away along with the process, the handlers still own the fds
+                break;
+            }
         }
     }
     
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Fake sysfs root with just a device-tree, removed when dropped
+    struct DtRoot(PathBuf);
+
+    impl DtRoot {
+        fn new(name: &str, compatible: &[&str], model: &str) -> DtRoot {
+            let root = std::env::temp_dir().join(format!("rinputer-test-{}-{}", std::process::id(), name));
+            let dt = root.join("proc/device-tree");
+            std::fs::create_dir_all(&dt).unwrap();
+            let compatible: String = compatible.iter().map(|c| format!("{}\0", c)).collect();
+            std::fs::write(dt.join("compatible"), compatible).unwrap();
+            std::fs::write(dt.join("model"), format!("{}\0", model)).unwrap();
+            DtRoot(root)
+        }
+    }
+
+    impl Drop for DtRoot {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn shipped_config() -> RinputerConfig {
+        load_config(&Path::new(env!("CARGO_MANIFEST_DIR")).join("rinputer3.ron")).unwrap()
+    }
+
+    fn dt_match(config: &RinputerConfig, root: &DtRoot) -> Option<String> {
+        let ids = DtIds::read(&root.0);
+        config.dt_strings.iter().find(|dev| ids.matches(dev)).map(|dev| dev.display_name.clone())
+    }
+
+    #[test]
+    fn odroid_entries_match() {
+        let config = shipped_config();
+        let cases = [
+            ("go2", "hardkernel,odroid-go2", "Odroid Go Advance"),
+            ("go2-v11", "hardkernel,odroid-go2-v11", "Odroid Go Advance Black Edition"),
+            ("go3", "hardkernel,odroid-go3", "Odroid Go Super"),
+        ];
+        for (name, compatible, display_name) in cases {
+            let root = DtRoot::new(name, &[compatible, "rockchip,rk3326"], display_name);
+            assert_eq!(dt_match(&config, &root).as_deref(), Some(display_name), "{}", compatible);
+        }
+
+        let root = DtRoot::new("other", &["rockchip,rk3326"], "Some other board");
+        assert_eq!(dt_match(&config, &root), None);
+    }
+
+    #[test]
+    fn machine_profile_uses_sysfs_root() {
+        let mut config = shipped_config();
+        config.dt_strings.push(DtStrings {
+            display_name: "Odroid Go Super, model only".to_string(),
+            compatible: "hardkernel,odroid-go3".to_string(),
+            model: Some("ODROID-GO Super".to_string()),
+            remap: vec![(InputRemap::Key(Key::BTN_SOUTH), InputRemap::Key(Key::BTN_EAST))],
+            deadzones: None,
+            imu_mount_matrix: None,
+        });
+        // the shipped entry for go3 comes first, move it out of the way to see model matching
+        config.dt_strings.retain(|dev| dev.model.is_some() || dev.compatible != "hardkernel,odroid-go3");
+
+        let root = DtRoot::new("profile", &["hardkernel,odroid-go3", "rockchip,rk3326"], "Hardkernel ODROID-GO Super");
+        let profile = machine_profile(&config, &root.0);
+        assert_eq!(profile.remap.len(), 1);
+
+        let root = DtRoot::new("profile-model", &["hardkernel,odroid-go3", "rockchip,rk3326"], "Hardkernel ODROID-GO3");
+        assert!(machine_profile(&config, &root.0).remap.is_empty());
+    }
+
+    #[test]
+    fn dt_matches_without_dmi_match() {
+        let mut config = shipped_config();
+        config.dt_strings.push(DtStrings {
+            display_name: "Board with DMI tables".to_string(),
+            compatible: "vendor,board-with-dmi".to_string(),
+            model: None,
+            remap: vec![(InputRemap::Key(Key::BTN_SOUTH), InputRemap::Key(Key::BTN_EAST))],
+            deadzones: None,
+            imu_mount_matrix: None,
+        });
+
+        let root = DtRoot::new("dmi", &["vendor,board-with-dmi"], "Board with DMI tables");
+        let dmi = root.0.join("sys/class/dmi/id");
+        std::fs::create_dir_all(&dmi).unwrap();
+        std::fs::write(dmi.join("product_name"), "Board with DMI tables\n").unwrap();
+        assert_eq!(machine_profile(&config, &root.0).remap.len(), 1);
+    }
+}
@@ -0,0 +1,129 @@
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+
+use evdev::InputEvent;
+use evdev::InputEventKind;
+use evdev::EventType;
+use evdev::Key;
+use evdev::AbsoluteAxisType;
+
+use crate::output_range;
+use crate::filter::DeviceMatch;
+use crate::registry::DeviceId;
+use crate::registry::Registry;
+
+/// How to combine several devices driving the same output
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub enum MergePolicy {
+    /// Whatever changed last wins
+    #[default]
+    LastWriter,
+    /// Value furthest away from rest position wins
+    LargestMagnitude,
+    /// All values are added together and clamped to output range
+    SumClamp,
+    /// First device in the list that isn't at rest wins, unlisted devices come last
+    Priority(Vec<DeviceMatch>),
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct MergeConfig {
+    #[serde(default)]
+    pub default: MergePolicy,
+    #[serde(default)]
+    pub axes: Vec<(AbsoluteAxisType, MergePolicy)>,
+    #[serde(default)]
+    pub keys: Vec<(Key, MergePolicy)>,
+}
+
+// (player slot, event type, event code)
+type Output = (usize, u16, u16);
+
+/// Remembers what every device last sent to every output
+pub struct Mixer {
+    config: MergeConfig,
+    sources: HashMap<Output, Vec<(DeviceId, i32)>>,
+}
+
+fn rank(list: &[DeviceMatch], registry: &Registry, id: DeviceId) -> usize {
+    registry.get(id)
+        .and_then(|info| list.iter().position(|m| m.matches_info(info)))
+        .unwrap_or(list.len())
+}
+
+impl Mixer {
+    pub fn new(config: MergeConfig) -> Mixer {
+        Mixer { config, sources: HashMap::new() }
+    }
+
+    fn policy(&self, ev: &InputEvent) -> &MergePolicy {
+        let found = match ev.kind() {
+            InputEventKind::AbsAxis(a) => self.config.axes.iter().find(|(x, _)| *x == a).map(|(_, p)| p),
+            InputEventKind::Key(k) => self.config.keys.iter().find(|(x, _)| *x == k).map(|(_, p)| p),
+            _ => None,
+        };
+        found.unwrap_or(&self.config.default)
+    }
+
+    /// Records `ev` as coming from `id` and returns what the output should be now
+    pub fn merge(&mut self, slot: usize, id: DeviceId, ev: InputEvent, registry: &Registry) -> InputEvent {
+        let policy = self.policy(&ev).clone();
+        let output = (slot, ev.event_type().0, ev.code());
+        let sources = self.sources.entry(output).or_default();
+        // most recent writer goes last
+        sources.retain(|(src, _)| *src != id);
+        sources.push((id, ev.value()));
+
+        let value = resolve(&policy, ev.kind(), sources, registry);
+        InputEvent::new(EventType(output.1), output.2, value)
+    }
+
+    /// Drops everything a device sent, returns new values of outputs it was holding
+    pub fn release(&mut self, id: DeviceId, registry: &Registry) -> Vec<(usize, InputEvent)> {
+        let mut released = Vec::new();
+        let outputs: Vec<Output> = self.sources.iter()
+            .filter(|(_, sources)| sources.iter().any(|(src, _)| *src == id))
+            .map(|(output, _)| *output)
+            .collect();
+
+        for output in outputs {
+            let template = InputEvent::new(EventType(output.1), output.2, 0);
+            let policy = self.policy(&template).clone();
+            let sources = self.sources.get_mut(&output).unwrap();
+            sources.retain(|(src, _)| *src != id);
+
+            let value = resolve(&policy, template.kind(), sources, registry);
+            released.push((output.0, InputEvent::new(EventType(output.1), output.2, value)));
+        }
+        released
+    }
+
+    /// Forgets every device, returns rest values of every output that was touched
+    pub fn neutral(&mut self) -> Vec<(usize, InputEvent)> {
+        self.sources.drain()
+            .map(|(output, _)| (output.0, InputEvent::new(EventType(output.1), output.2, 0)))
+            .collect()
+    }
+}
+
+fn resolve(policy: &MergePolicy, kind: InputEventKind, sources: &[(DeviceId, i32)], registry: &Registry) -> i32 {
+    match policy {
+        MergePolicy::LastWriter => sources.last().map_or(0, |(_, v)| *v),
+        MergePolicy::LargestMagnitude => sources.iter()
+            .map(|(_, v)| *v)
+            .max_by_key(|v| v.abs())
+            .unwrap_or(0),
+        MergePolicy::SumClamp => {
+            let (min, max) = match kind {
+                InputEventKind::AbsAxis(a) => output_range(a),
+                _ => (0, 1),
+            };
+            sources.iter().map(|(_, v)| *v as i64).sum::<i64>().clamp(min as i64, max as i64) as i32
+        },
+        MergePolicy::Priority(list) => sources.iter()
+            .filter(|(_, v)| *v != 0)
+            .min_by_key(|(src, _)| rank(list, registry, *src))
+            .map_or(0, |(_, v)| *v),
+    }
+}
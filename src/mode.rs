@@ -0,0 +1,97 @@
+use std::fmt;
+use std::fs;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+use anyhow::Result;
+use anyhow::Context;
+
+/// What the controls drive
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Mode {
+    /// Virtual pad, through the regular remap table
+    #[default]
+    Gamepad,
+    /// Virtual mouse and keyboard, through `desktop_remap`, the pad gets nothing
+    Desktop,
+}
+
+impl fmt::Display for Mode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Mode::Gamepad => write!(f, "gamepad"),
+            Mode::Desktop => write!(f, "desktop"),
+        }
+    }
+}
+
+impl FromStr for Mode {
+    type Err = ();
+    fn from_str(input: &str) -> Result<Mode, ()> {
+        match input.trim() {
+            "gamepad" => Ok(Mode::Gamepad),
+            "desktop" => Ok(Mode::Desktop),
+            _ => Err(()),
+        }
+    }
+}
+
+fn default_state_file() -> PathBuf {PathBuf::from("/var/lib/rinputer3/mode")}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ModeConfig {
+    /// Where the active mode is kept across restarts
+    #[serde(default = "default_state_file")]
+    pub state_file: PathBuf,
+}
+
+impl Default for ModeConfig {
+    fn default() -> ModeConfig {
+        ModeConfig {
+            state_file: default_state_file(),
+        }
+    }
+}
+
+/// Tracks the active mode, switched through IPC or a `ToggleMode` remap
+pub struct ModeSwitch {
+    mode: Mode,
+    config: ModeConfig,
+}
+
+impl ModeSwitch {
+    /// Picks up the mode from the last run, gamepad if there's none
+    pub fn new(config: ModeConfig) -> ModeSwitch {
+        let mode = fs::read_to_string(&config.state_file).ok()
+            .and_then(|s| Mode::from_str(&s).ok())
+            .unwrap_or_default();
+        println!("Starting in {} mode", mode);
+        ModeSwitch {
+            mode,
+            config,
+        }
+    }
+
+    pub fn mode(&self) -> Mode {
+        self.mode
+    }
+
+    /// Switches to `mode`, or to the other one on None, and saves it
+    pub fn set(&mut self, mode: Option<Mode>) -> Result<()> {
+        self.mode = mode.unwrap_or(match self.mode {
+            Mode::Gamepad => Mode::Desktop,
+            Mode::Desktop => Mode::Gamepad,
+        });
+        println!("Switching to {} mode", self.mode);
+
+        let path = &self.config.state_file;
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)
+                .with_context(|| format!("Failed creating {}", dir.display()))?;
+        }
+        fs::write(path, self.mode.to_string())
+            .with_context(|| format!("Failed saving mode to {}", path.display()))?;
+        Ok(())
+    }
+}
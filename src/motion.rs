@@ -0,0 +1,168 @@
+use std::path::Path;
+use std::sync::mpsc::Sender;
+
+use anyhow::Result;
+use anyhow::Context;
+
+use evdev::AbsInfo;
+use evdev::AbsoluteAxisType;
+use evdev::Device;
+use evdev::EventType;
+use evdev::InputEvent;
+use evdev::InputEventKind;
+use evdev::InputId;
+use evdev::MiscType;
+use evdev::PropType;
+use evdev::Synchronization;
+use evdev::UinputAbsSetup;
+use evdev::uinput::VirtualDevice;
+use evdev::uinput::VirtualDeviceBuilder;
+
+use crate::RinputerEvent;
+use crate::hotplug;
+use crate::RINPUTER_VERSION;
+use crate::registry::DeviceId;
+use crate::registry::DeviceInfo;
+
+/// Rotates sensor axes into the pad's frame, rows are output X, Y and Z, same as udev's ACCEL_MOUNT_MATRIX
+pub type MountMatrix = [[f32; 3]; 3];
+
+// same units hid-playstation uses, which is what most things reading motion expect
+pub const ACCEL_RES: i32 = 8192; // per g
+pub const GYRO_RES: i32 = 1024; // per degree per second
+const ACCEL_RANGE: i32 = 4 * ACCEL_RES;
+pub const GYRO_RANGE: i32 = 2048 * GYRO_RES;
+
+static ACCEL_AXES: [AbsoluteAxisType; 3] = [AbsoluteAxisType::ABS_X, AbsoluteAxisType::ABS_Y, AbsoluteAxisType::ABS_Z];
+static GYRO_AXES: [AbsoluteAxisType; 3] = [AbsoluteAxisType::ABS_RX, AbsoluteAxisType::ABS_RY, AbsoluteAxisType::ABS_RZ];
+
+pub fn is_motion(dev: &Device) -> bool {
+    dev.properties().contains(PropType::ACCELEROMETER)
+}
+
+/// One sensor report, already in `ACCEL_RES` and `GYRO_RES` units and rotated into the pad's frame
+#[derive(Debug, Clone, Copy, Default)]
+pub struct MotionFrame {
+    pub accel: [i32; 3],
+    pub gyro: [i32; 3],
+    /// Microseconds, wraps around
+    pub timestamp: Option<i32>,
+}
+
+impl MotionFrame {
+    pub fn events(&self) -> Vec<InputEvent> {
+        let mut events: Vec<InputEvent> = ACCEL_AXES.iter().zip(self.accel)
+            .chain(GYRO_AXES.iter().zip(self.gyro))
+            .map(|(axis, v)| InputEvent::new(EventType::ABSOLUTE, axis.0, v))
+            .collect();
+        if let Some(ts) = self.timestamp {
+            events.push(InputEvent::new(EventType::MISC, MiscType::MSC_TIMESTAMP.0, ts));
+        }
+        events
+    }
+}
+
+fn rotate(matrix: &MountMatrix, v: [f32; 3]) -> [i32; 3] {
+    let mut out = [0; 3];
+    for (o, row) in out.iter_mut().zip(matrix) {
+        *o = (row[0] * v[0] + row[1] * v[1] + row[2] * v[2]).round() as i32;
+    }
+    out
+}
+
+/// How to turn raw values of an axis into output units
+#[derive(Debug, Clone, Copy)]
+struct Scale {
+    factor: f32,
+    center: f32,
+}
+
+impl Scale {
+    fn new(min: i32, max: i32, resolution: i32, out_res: i32, out_range: i32) -> Scale {
+        if resolution > 0 {
+            Scale { factor: out_res as f32 / resolution as f32, center: 0.0 }
+        } else if max > min {
+            // no resolution, all we can do is assume full range of the sensor is full range of ours
+            Scale {
+                factor: 2.0 * out_range as f32 / (max - min) as f32,
+                center: (max + min) as f32 / 2.0,
+            }
+        } else {
+            Scale { factor: 1.0, center: 0.0 }
+        }
+    }
+
+    fn apply(&self, value: i32) -> f32 {
+        (value as f32 - self.center) * self.factor
+    }
+}
+
+/// Reads a motion sensor, without grabbing it, so things like screen rotation keep working
+pub fn motion_handler(tx: Sender<RinputerEvent>, id: DeviceId, path: &Path, mut dev: Device, mount: Option<MountMatrix>) -> Result<()> {
+    println!("Motion sensor {} found", dev.name().unwrap_or("<invalid name>"));
+    let _connection = hotplug::connect(&tx, id, DeviceInfo::new(path, &dev, false))?;
+
+    let mount = mount.unwrap_or([[1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0]]);
+    let absinfo = dev.get_abs_state()?;
+    let scale = |axis: AbsoluteAxisType, res, range| {
+        let info = absinfo[axis.0 as usize];
+        Scale::new(info.minimum, info.maximum, info.resolution, res, range)
+    };
+    let accel_scale = ACCEL_AXES.map(|a| scale(a, ACCEL_RES, ACCEL_RANGE));
+    let gyro_scale = GYRO_AXES.map(|a| scale(a, GYRO_RES, GYRO_RANGE));
+
+    let mut accel = [0.0; 3];
+    let mut gyro = [0.0; 3];
+    let mut timestamp = None;
+
+    loop {
+        for ev in dev.fetch_events()? {
+            match ev.kind() {
+                InputEventKind::AbsAxis(a) => {
+                    if let Some(i) = ACCEL_AXES.iter().position(|x| *x == a) {
+                        accel[i] = accel_scale[i].apply(ev.value());
+                    } else if let Some(i) = GYRO_AXES.iter().position(|x| *x == a) {
+                        gyro[i] = gyro_scale[i].apply(ev.value());
+                    }
+                },
+                InputEventKind::Misc(MiscType::MSC_TIMESTAMP) => timestamp = Some(ev.value()),
+                InputEventKind::Synchronization(Synchronization::SYN_REPORT) => {
+                    let frame = MotionFrame {
+                        accel: rotate(&mount, accel),
+                        gyro: rotate(&mount, gyro),
+                        timestamp: timestamp.take(),
+                    };
+                    tx.send(RinputerEvent::Motion(id, frame))?;
+                },
+                _ => (),
+            }
+        }
+    }
+}
+
+/// Motion sensor half of a virtual pad, named and numbered after it so that it gets paired with it
+pub fn create_motion(name: &str, vendor: u16, product: u16) -> Result<VirtualDevice> {
+    let mut props = evdev::AttributeSet::<PropType>::new();
+    props.insert(PropType::ACCELEROMETER);
+    let mut msc = evdev::AttributeSet::<MiscType>::new();
+    msc.insert(MiscType::MSC_TIMESTAMP);
+
+    let name = format!("{} Motion Sensors", name);
+    let mut builder = VirtualDeviceBuilder::new()
+        .context("Failed to create instance of evdev::VirtualDeviceBuilder")?
+        .name(name.as_bytes())
+        .input_id(InputId::new(evdev::BusType::BUS_USB, vendor, product, RINPUTER_VERSION))
+        .with_properties(&props)?
+        .with_msc(&msc)?;
+
+    let abs_accel = AbsInfo::new(0, -ACCEL_RANGE, ACCEL_RANGE, 4, 0, ACCEL_RES);
+    for axis in ACCEL_AXES {
+        builder = builder.with_absolute_axis(&UinputAbsSetup::new(axis, abs_accel))?;
+    }
+    let abs_gyro = AbsInfo::new(0, -GYRO_RANGE, GYRO_RANGE, 16, 0, GYRO_RES);
+    for axis in GYRO_AXES {
+        builder = builder.with_absolute_axis(&UinputAbsSetup::new(axis, abs_gyro))?;
+    }
+
+    builder.build().context("Failed to create uinput motion device")
+}
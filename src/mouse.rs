@@ -0,0 +1,236 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use anyhow::Result;
+use anyhow::Context;
+
+use evdev::AbsoluteAxisType;
+use evdev::EventType;
+use evdev::InputEvent;
+use evdev::InputId;
+use evdev::BusType;
+use evdev::Key;
+use evdev::RelativeAxisType;
+use evdev::uinput::VirtualDevice;
+use evdev::uinput::VirtualDeviceBuilder;
+
+use crate::curve::ResponseCurve;
+use crate::output_range;
+use crate::RINPUTER_VERSION;
+use crate::buttons::Buttons;
+use crate::registry::DeviceId;
+
+const TICK: Duration = Duration::from_millis(10);
+
+static BUTTONS: [Key; 5] = [Key::BTN_LEFT, Key::BTN_RIGHT, Key::BTN_MIDDLE, Key::BTN_SIDE, Key::BTN_EXTRA];
+
+pub fn is_button(key: Key) -> bool {
+    BUTTONS.contains(&key)
+}
+
+fn default_pointer_speed() -> f32 {1500.0}
+fn default_scroll_speed() -> f32 {15.0}
+fn default_acceleration() -> ResponseCurve {ResponseCurve::Power(2.0)}
+fn default_rest() -> f32 {0.1}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MouseConfig {
+    /// Pixels per second at full deflection
+    #[serde(default = "default_pointer_speed")]
+    pub pointer_speed: f32,
+    /// Wheel notches per second at full deflection
+    #[serde(default = "default_scroll_speed")]
+    pub scroll_speed: f32,
+    /// Maps stick deflection to fraction of full speed
+    #[serde(default = "default_acceleration")]
+    pub acceleration: ResponseCurve,
+    /// Deflection, 0 to 1, that still counts as at rest, so stick noise doesn't move anything
+    #[serde(default = "default_rest")]
+    pub rest: f32,
+}
+
+impl Default for MouseConfig {
+    fn default() -> MouseConfig {
+        MouseConfig {
+            pointer_speed: default_pointer_speed(),
+            scroll_speed: default_scroll_speed(),
+            acceleration: default_acceleration(),
+            rest: default_rest(),
+        }
+    }
+}
+
+fn create_mouse() -> Result<VirtualDevice> {
+    let mut keys = evdev::AttributeSet::<Key>::new();
+    for k in BUTTONS {
+        keys.insert(k);
+    }
+
+    let mut rel = evdev::AttributeSet::<RelativeAxisType>::new();
+    for r in [RelativeAxisType::REL_X, RelativeAxisType::REL_Y, RelativeAxisType::REL_WHEEL, RelativeAxisType::REL_HWHEEL] {
+        rel.insert(r);
+    }
+
+    VirtualDeviceBuilder::new()
+        .context("Failed to create instance of evdev::VirtualDeviceBuilder")?
+        .name(b"Rinputer Mouse")
+        .input_id(InputId::new(BusType::BUS_USB, 0x0000, 0x0000, RINPUTER_VERSION))
+        .with_keys(&keys)?
+        .with_relative_axes(&rel)?
+        .build()
+        .context("Failed to create uinput mouse")
+}
+
+struct MouseState {
+    dev: VirtualDevice,
+    // REL axis -> (device driving it, deflection in -1..1)
+    axes: HashMap<u16, (DeviceId, f32)>,
+    // sub-pixel leftovers, so slow movement isn't lost to rounding
+    remainder: HashMap<u16, f32>,
+}
+
+impl MouseState {
+    fn tick(&mut self, config: &MouseConfig) -> Result<()> {
+        let dt = TICK.as_secs_f32();
+        let mut out = Vec::new();
+        for (code, (_, deflection)) in &self.axes {
+            let speed = match RelativeAxisType(*code) {
+                RelativeAxisType::REL_X | RelativeAxisType::REL_Y => config.pointer_speed,
+                _ => config.scroll_speed,
+            };
+            let rem = self.remainder.entry(*code).or_insert(0.0);
+            *rem += config.acceleration.eval(deflection.abs()).copysign(*deflection) * speed * dt;
+            let whole = rem.trunc();
+            if whole != 0.0 {
+                *rem -= whole;
+                out.push(InputEvent::new(EventType::RELATIVE, *code, whole as i32));
+            }
+        }
+        if !out.is_empty() {
+            self.dev.emit(&out)?;
+        }
+        Ok(())
+    }
+}
+
+fn ticker(state: Arc<Mutex<MouseState>>, config: MouseConfig) -> Result<()> {
+    loop {
+        thread::sleep(TICK);
+        state.lock().unwrap().tick(&config)?;
+    }
+}
+
+/// Virtual mouse, only created once something gets remapped onto it
+///
+/// Sticks set a deflection, the pointer keeps moving on its own until they're back at rest.
+pub struct Mouse {
+    config: MouseConfig,
+    state: Option<Arc<Mutex<MouseState>>>,
+    buttons: Buttons,
+}
+
+impl Mouse {
+    pub fn new(config: MouseConfig) -> Mouse {
+        Mouse {
+            config,
+            state: None,
+            buttons: Buttons::new(),
+        }
+    }
+
+    fn state(&mut self) -> Result<Arc<Mutex<MouseState>>> {
+        if let Some(ref state) = self.state {
+            return Ok(state.clone());
+        }
+
+        println!("Creating virtual mouse");
+        let state = Arc::new(Mutex::new(MouseState {
+            dev: create_mouse()?,
+            axes: HashMap::new(),
+            remainder: HashMap::new(),
+        }));
+        let (ticker_state, config) = (state.clone(), self.config.clone());
+        thread::spawn(move || {
+            if let Err(e) = ticker(ticker_state, config) {
+                eprintln!("Virtual mouse stopped moving: {:#}", e);
+            }
+        });
+        self.state = Some(state.clone());
+        Ok(state)
+    }
+
+    /// Drives `rel` with the position of `axis`, a value at rest stops it
+    pub fn set_axis(&mut self, id: DeviceId, rel: RelativeAxisType, axis: AbsoluteAxisType, value: i32) -> Result<()> {
+        let (min, max) = output_range(axis);
+        let mut deflection = if min < 0 {
+            value as f32 / if value < 0 { -(min as f32) } else { max as f32 }
+        } else {
+            (value - min) as f32 / (max - min) as f32
+        };
+        // stick up is negative, wheel up is positive
+        if rel == RelativeAxisType::REL_WHEEL {
+            deflection = -deflection;
+        }
+        // what's left past rest is stretched back to the full range
+        let rest = self.config.rest.clamp(0.0, 0.99);
+        deflection = if deflection.abs() <= rest {
+            0.0
+        } else {
+            ((deflection.abs() - rest) / (1.0 - rest)).copysign(deflection)
+        };
+
+        let state = self.state()?;
+        let mut state = state.lock().unwrap();
+        if deflection == 0.0 {
+            state.axes.remove(&rel.0);
+            state.remainder.remove(&rel.0);
+        } else {
+            state.axes.insert(rel.0, (id, deflection.clamp(-1.0, 1.0)));
+        }
+        Ok(())
+    }
+
+    /// Moves the pointer right away, fractions of a pixel are kept for later
+    pub fn move_by(&mut self, dx: f32, dy: f32) -> Result<()> {
+        let state = self.state()?;
+        let mut state = state.lock().unwrap();
+        let mut out = Vec::new();
+        for (rel, delta) in [(RelativeAxisType::REL_X, dx), (RelativeAxisType::REL_Y, dy)] {
+            let rem = state.remainder.entry(rel.0).or_insert(0.0);
+            *rem += delta;
+            let whole = rem.trunc();
+            if whole != 0.0 {
+                *rem -= whole;
+                out.push(InputEvent::new(EventType::RELATIVE, rel.0, whole as i32));
+            }
+        }
+        if !out.is_empty() {
+            state.dev.emit(&out)?;
+        }
+        Ok(())
+    }
+
+    pub fn button(&mut self, id: DeviceId, key: Key, value: i32) -> Result<()> {
+        self.buttons.set(id, key, value);
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> Result<()> {
+        if let Some(frame) = self.buttons.take() {
+            self.state()?.lock().unwrap().dev.emit(&frame)?;
+        }
+        Ok(())
+    }
+
+    /// Stops everything `id` was doing, or everything at all on None
+    pub fn release(&mut self, id: Option<DeviceId>) -> Result<()> {
+        if let Some(ref state) = self.state {
+            state.lock().unwrap().axes.retain(|_, (owner, _)| id.map_or(false, |id| *owner != id));
+        }
+        self.buttons.release(id);
+        self.flush()
+    }
+}
@@ -0,0 +1,168 @@
+use std::collections::HashMap;
+use std::sync::mpsc::Sender;
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use anyhow::Result;
+use evdev::Key;
+use evdev::InputEvent;
+use evdev::uinput::VirtualDevice;
+
+use crate::RinputerEvent;
+use crate::motion::MotionFrame;
+use crate::profile::OutputProfile;
+use crate::rumble;
+use crate::registry::DeviceId;
+use crate::registry::DeviceInfo;
+use crate::registry::Registry;
+
+/// Hands out virtual pads to physical devices
+///
+/// In the default mode everything goes into a single pad. In multiplayer mode every
+/// gamepad gets its own pad, and remembers its player slot across reconnects.
+/// Devices that aren't gamepads, like extra buttons on handhelds, always go to player 1.
+pub struct Players {
+    multiplayer: bool,
+    profile: OutputProfile,
+    extra_buttons: Vec<Key>,
+    // sticks rinputer applies a deadzone to, left and right
+    filtered: [bool; 2],
+    // shared with the rumble thread of each pad
+    pads: Vec<Arc<Mutex<VirtualDevice>>>,
+    // motion half of each pad, made once a sensor shows up
+    motion: Vec<Option<VirtualDevice>>,
+    tx: Sender<RinputerEvent>,
+    // device identity -> slot it had last time
+    known: HashMap<String, usize>,
+    active: HashMap<DeviceId, usize>,
+    // events of the frame each device is in the middle of
+    pending: HashMap<DeviceId, Vec<(usize, InputEvent)>>,
+}
+
+fn same_controller(a: &DeviceInfo, b: &DeviceInfo) -> bool {
+    match (&a.uniq, &b.uniq) {
+        (Some(a), Some(b)) => a == b,
+        _ => a.phys.is_some() && a.phys == b.phys,
+    }
+}
+
+fn identity(info: &DeviceInfo) -> String {
+    format!("{:04x}:{:04x}:{}", info.input_id.vendor(), info.input_id.product(),
+        info.uniq.as_ref().or(info.phys.as_ref()).unwrap_or(&info.name))
+}
+
+impl Players {
+    pub fn new(multiplayer: bool, profile: OutputProfile, extra_buttons: Vec<Key>, filtered: [bool; 2], tx: Sender<RinputerEvent>) -> Result<Players> {
+        let mut players = Players {
+            multiplayer,
+            profile,
+            extra_buttons,
+            filtered,
+            pads: Vec::new(),
+            motion: Vec::new(),
+            tx,
+            known: HashMap::new(),
+            active: HashMap::new(),
+            pending: HashMap::new(),
+        };
+        players.add_pad()?;
+        Ok(players)
+    }
+
+    fn add_pad(&mut self) -> Result<()> {
+        let slot = self.pads.len();
+        let pad = Arc::new(Mutex::new(self.profile.build(&self.extra_buttons, self.filtered)?));
+        let (tx, ff_pad) = (self.tx.clone(), pad.clone());
+        thread::spawn(move || {
+            if let Err(e) = rumble::ff_watcher(tx, slot, ff_pad) {
+                eprintln!("Rumble on player {} stopped: {:#}", slot + 1, e);
+            }
+        });
+        self.pads.push(pad);
+        self.motion.push(None);
+        Ok(())
+    }
+
+    pub fn connect(&mut self, id: DeviceId, info: &DeviceInfo, registry: &Registry) -> Result<()> {
+        if self.multiplayer && info.motion {
+            // sensors go with the controller they're built into
+            let paired = registry.iter()
+                .find(|(_, other)| !other.motion && same_controller(info, other))
+                .map(|(other, _)| self.slot(*other));
+            if let Some(slot) = paired {
+                println!("Device {} is motion for player {}", id, slot + 1);
+                self.active.insert(id, slot);
+            }
+            return Ok(());
+        }
+        if !self.multiplayer || !info.keys.contains(&Key::BTN_SOUTH) {
+            return Ok(());
+        }
+
+        let ident = identity(info);
+        let taken: Vec<usize> = self.active.values().copied().collect();
+        let slot = match self.known.get(&ident) {
+            // a returning controller gets its old slot back if nobody took it in the meantime
+            Some(slot) if !taken.contains(slot) => *slot,
+            _ => (0..).find(|s| !taken.contains(s)).unwrap(),
+        };
+
+        while self.pads.len() <= slot {
+            self.add_pad()?;
+        }
+        println!("Device {} is player {}", id, slot + 1);
+        self.known.insert(ident, slot);
+        self.active.insert(id, slot);
+        Ok(())
+    }
+
+    pub fn disconnect(&mut self, id: DeviceId) {
+        // half a frame is worse than none
+        self.pending.remove(&id);
+        self.active.remove(&id);
+    }
+
+    pub fn slot(&self, id: DeviceId) -> usize {
+        self.active.get(&id).copied().unwrap_or(0)
+    }
+
+    /// Takes an event in internal 360 layout and sends it out of the pad in `slot` right away
+    pub fn emit(&mut self, slot: usize, ev: InputEvent) -> Result<()> {
+        self.pads[slot].lock().unwrap().emit(&self.profile.convert(ev))?;
+        Ok(())
+    }
+
+    pub fn emit_motion(&mut self, slot: usize, frame: &MotionFrame) -> Result<()> {
+        if self.motion[slot].is_none() {
+            println!("Creating motion device for player {}", slot + 1);
+            self.motion[slot] = Some(self.profile.build_motion()?);
+        }
+        self.motion[slot].as_mut().unwrap().emit(&frame.events())?;
+        Ok(())
+    }
+
+    /// Same as `emit`, but holds the event back until `id` ends its frame
+    pub fn queue(&mut self, id: DeviceId, slot: usize, ev: InputEvent) {
+        self.pending.entry(id).or_default().push((slot, ev));
+    }
+
+    /// Sends everything `id` queued since its last SYN_REPORT, as a single frame on every pad it touched
+    pub fn flush(&mut self, id: DeviceId) -> Result<()> {
+        let pending = match self.pending.remove(&id) {
+            Some(pending) => pending,
+            None => return Ok(()),
+        };
+
+        let mut slots: Vec<usize> = pending.iter().map(|(slot, _)| *slot).collect();
+        slots.sort_unstable();
+        slots.dedup();
+        for slot in slots {
+            let frame: Vec<InputEvent> = pending.iter()
+                .filter(|(s, _)| *s == slot)
+                .flat_map(|(_, ev)| self.profile.convert(*ev))
+                .collect();
+            self.pads[slot].lock().unwrap().emit(&frame)?;
+        }
+        Ok(())
+    }
+}
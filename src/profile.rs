@@ -0,0 +1,249 @@
+use serde::{Deserialize, Serialize};
+
+use anyhow::Result;
+use anyhow::Context;
+
+use evdev::InputEvent;
+use evdev::InputEventKind;
+use evdev::EventType;
+use evdev::Key;
+use evdev::FFEffectType;
+use evdev::AbsoluteAxisType;
+use evdev::InputId;
+use evdev::BusType;
+use evdev::UinputAbsSetup;
+use evdev::AbsInfo;
+use evdev::uinput::VirtualDevice;
+use evdev::uinput::VirtualDeviceBuilder;
+
+use crate::remap;
+use crate::output_range;
+use crate::MIN_OUT_HAT;
+use crate::MAX_OUT_HAT;
+use crate::RINPUTER_VERSION;
+use crate::MIN_OUT_ANALOG;
+use crate::MAX_OUT_ANALOG;
+
+/// What the virtual pad pretends to be
+///
+/// Internally everything is kept in Xbox 360 layout and ranges, events are converted
+/// right before they're emitted.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub enum OutputProfile {
+    #[default]
+    Xbox360,
+    XboxOne,
+    XboxSeries,
+    DualShock4,
+    DualSense,
+    SwitchPro,
+    Generic,
+}
+
+struct Layout {
+    name: &'static str,
+    vendor: u16,
+    product: u16,
+    /// On top of the 11 buttons of a 360 pad
+    extra_keys: &'static [Key],
+    /// Back paddles and such, used when `extra_buttons` isn't set
+    paddles: &'static [Key],
+    stick: (i32, i32),
+    /// None when the pad has digital triggers only
+    trigger: Option<(i32, i32)>,
+    /// Digital trigger buttons, pressed when the trigger is past this value
+    trigger_buttons: Option<i32>,
+}
+
+static XBOX_KEYS: [Key; 11] = [Key::BTN_SOUTH, Key::BTN_EAST, Key::BTN_NORTH, Key::BTN_WEST,
+    Key::BTN_TL, Key::BTN_TR, Key::BTN_SELECT, Key::BTN_START, Key::BTN_MODE, Key::BTN_THUMBL,
+    Key::BTN_THUMBR];
+
+// how xpad reports the back paddles of Elite controllers
+static ELITE_PADDLES: [Key; 4] = [Key::BTN_TRIGGER_HAPPY5, Key::BTN_TRIGGER_HAPPY6, Key::BTN_TRIGGER_HAPPY7,
+    Key::BTN_TRIGGER_HAPPY8];
+
+impl OutputProfile {
+    fn layout(self) -> Layout {
+        match self {
+            OutputProfile::Xbox360 => Layout {
+                name: "Microsoft X-Box 360 pad",
+                vendor: 0x045e,
+                product: 0x028e,
+                extra_keys: &[],
+                paddles: &[],
+                stick: (-32768, 32767),
+                trigger: Some((0, 255)),
+                trigger_buttons: None,
+            },
+            OutputProfile::XboxOne => Layout {
+                name: "Microsoft X-Box One S pad",
+                vendor: 0x045e,
+                product: 0x02ea,
+                extra_keys: &[],
+                paddles: &ELITE_PADDLES,
+                stick: (-32768, 32767),
+                trigger: Some((0, 1023)),
+                trigger_buttons: None,
+            },
+            OutputProfile::XboxSeries => Layout {
+                name: "Microsoft Xbox Series S|X Controller",
+                vendor: 0x045e,
+                product: 0x0b12,
+                // share button
+                extra_keys: &[Key::KEY_RECORD],
+                paddles: &ELITE_PADDLES,
+                stick: (-32768, 32767),
+                trigger: Some((0, 1023)),
+                trigger_buttons: None,
+            },
+            OutputProfile::DualShock4 => Layout {
+                name: "Sony Interactive Entertainment Wireless Controller",
+                vendor: 0x054c,
+                product: 0x09cc,
+                extra_keys: &[Key::BTN_TL2, Key::BTN_TR2],
+                paddles: &[],
+                stick: (0, 255),
+                trigger: Some((0, 255)),
+                trigger_buttons: Some(0),
+            },
+            OutputProfile::DualSense => Layout {
+                name: "Sony Interactive Entertainment DualSense Wireless Controller",
+                vendor: 0x054c,
+                product: 0x0ce6,
+                extra_keys: &[Key::BTN_TL2, Key::BTN_TR2],
+                paddles: &[],
+                stick: (0, 255),
+                trigger: Some((0, 255)),
+                trigger_buttons: Some(0),
+            },
+            OutputProfile::SwitchPro => Layout {
+                name: "Nintendo Switch Pro Controller",
+                vendor: 0x057e,
+                product: 0x2009,
+                // capture button
+                extra_keys: &[Key::BTN_TL2, Key::BTN_TR2, Key::BTN_Z],
+                paddles: &[],
+                stick: (-32767, 32767),
+                trigger: None,
+                trigger_buttons: Some(127),
+            },
+            OutputProfile::Generic => Layout {
+                name: "Rinputer Gamepad",
+                vendor: 0x0000,
+                product: 0x0000,
+                extra_keys: &[Key::BTN_TL2, Key::BTN_TR2],
+                paddles: &[Key::BTN_TRIGGER_HAPPY1, Key::BTN_TRIGGER_HAPPY2, Key::BTN_TRIGGER_HAPPY3,
+                    Key::BTN_TRIGGER_HAPPY4, Key::BTN_C, Key::BTN_Z],
+                stick: (-32767, 32767),
+                trigger: Some((0, 255)),
+                trigger_buttons: Some(127),
+            },
+        }
+    }
+
+    /// Buttons the pad has on top of what every pad of this kind has, used when `extra_buttons` isn't set
+    pub fn default_extra_buttons(self) -> Vec<Key> {
+        self.layout().paddles.to_vec()
+    }
+
+    /// Every button the pad built with `extra` ends up with
+    pub fn keys(self, extra: &[Key]) -> Vec<Key> {
+        let layout = self.layout();
+        let mut keys: Vec<Key> = XBOX_KEYS.iter().chain(layout.extra_keys).chain(extra).copied().collect();
+        keys.sort_by_key(|k| k.code());
+        keys.dedup();
+        keys
+    }
+
+    /// `filtered` says which of the left and right stick rinputer applies a deadzone to
+    pub fn build(self, extra: &[Key], filtered: [bool; 2]) -> Result<VirtualDevice> {
+        let layout = self.layout();
+
+        let mut keys = evdev::AttributeSet::<Key>::new();
+        for k in self.keys(extra) {
+            keys.insert(k);
+        }
+
+        let input_id = InputId::new(BusType::BUS_USB, layout.vendor, layout.product, RINPUTER_VERSION);
+
+        let mut ff = evdev::AttributeSet::<FFEffectType>::new();
+        ff.insert(FFEffectType::FF_RUMBLE);
+        ff.insert(FFEffectType::FF_GAIN);
+
+        let mut builder = VirtualDeviceBuilder::new()
+            .context("Failed to create instance of evdev::VirtualDeviceBuilder")?
+            .name(layout.name.as_bytes())
+            .input_id(input_id)
+            .with_keys(&keys)?
+            .with_ff(&ff)?
+            .with_ff_effects_max(16);
+
+        // fuzz and flat are what the 360 pad always had, scaled to the profile's range, which leaves
+        // no fuzz on 8-bit sticks, same as hid-playstation
+        let scale = |v: i32| v * (layout.stick.1 - layout.stick.0) / (MAX_OUT_ANALOG - MIN_OUT_ANALOG);
+        let sticks = [
+            (filtered[0], [AbsoluteAxisType::ABS_X, AbsoluteAxisType::ABS_Y]),
+            (filtered[1], [AbsoluteAxisType::ABS_RX, AbsoluteAxisType::ABS_RY]),
+        ];
+        for (filtered, axes) in sticks {
+            // a stick with a deadzone in rinputer gets no flat, games would add their own on top
+            let flat = if filtered { 0 } else { scale(256) };
+            let abs_analogs = AbsInfo::new(0, layout.stick.0, layout.stick.1, scale(16), flat, 0);
+            for axis in axes {
+                builder = builder.with_absolute_axis(&UinputAbsSetup::new(axis, abs_analogs))?;
+            }
+        }
+
+        if let Some((min, max)) = layout.trigger {
+            let abs_triggers = AbsInfo::new(0, min, max, 0, 0, 0);
+            for axis in [AbsoluteAxisType::ABS_Z, AbsoluteAxisType::ABS_RZ] {
+                builder = builder.with_absolute_axis(&UinputAbsSetup::new(axis, abs_triggers))?;
+            }
+        }
+
+        let abs_hat = AbsInfo::new(0, MIN_OUT_HAT, MAX_OUT_HAT, 0, 0, 0);
+        for axis in [AbsoluteAxisType::ABS_HAT0X, AbsoluteAxisType::ABS_HAT0Y] {
+            builder = builder.with_absolute_axis(&UinputAbsSetup::new(axis, abs_hat))?;
+        }
+
+        builder.build().context("Failed to create uinput device")
+    }
+
+    pub fn build_motion(self) -> Result<VirtualDevice> {
+        let layout = self.layout();
+        crate::motion::create_motion(layout.name, layout.vendor, layout.product)
+    }
+
+    /// Converts an event from internal 360 layout into this profile
+    pub fn convert(self, ev: InputEvent) -> Vec<InputEvent> {
+        let axis = match ev.kind() {
+            InputEventKind::AbsAxis(a) => a,
+            _ => return vec![ev],
+        };
+        let layout = self.layout();
+        let (min, max) = output_range(axis);
+        let mut out = Vec::with_capacity(2);
+
+        match axis {
+            AbsoluteAxisType::ABS_Z | AbsoluteAxisType::ABS_RZ => {
+                if let Some(threshold) = layout.trigger_buttons {
+                    let button = if axis == AbsoluteAxisType::ABS_Z { Key::BTN_TL2 } else { Key::BTN_TR2 };
+                    out.push(InputEvent::new(EventType::KEY, button.code(), (ev.value() > threshold) as i32));
+                }
+                if let Some((outmin, outmax)) = layout.trigger {
+                    let value = remap(ev.value(), min, max, outmin, outmax).clamp(outmin, outmax);
+                    out.push(InputEvent::new(EventType::ABSOLUTE, ev.code(), value));
+                }
+            },
+            AbsoluteAxisType::ABS_X | AbsoluteAxisType::ABS_Y | AbsoluteAxisType::ABS_RX | AbsoluteAxisType::ABS_RY => {
+                let (outmin, outmax) = layout.stick;
+                // remaps onto an axis can go past the 360 range, and remap() doesn't stop at the ends
+                let value = remap(ev.value(), min, max, outmin, outmax).clamp(outmin, outmax);
+                out.push(InputEvent::new(EventType::ABSOLUTE, ev.code(), value));
+            },
+            _ => out.push(ev),
+        }
+        out
+    }
+}
@@ -0,0 +1,119 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::time::Instant;
+
+use evdev::Device;
+use evdev::InputId;
+use evdev::Key;
+use evdev::AbsoluteAxisType;
+use evdev::PropType;
+
+static NEXT_ID: AtomicU32 = AtomicU32::new(1);
+
+/// Handle for a physical device, stays valid until the device is disconnected
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct DeviceId(u32);
+
+impl DeviceId {
+    /// Events generated by rinputer itself, not coming from any physical device
+    pub const INTERNAL: DeviceId = DeviceId(0);
+
+    pub fn next() -> DeviceId {
+        DeviceId(NEXT_ID.fetch_add(1, Ordering::Relaxed))
+    }
+}
+
+impl fmt::Display for DeviceId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "#{}", self.0)
+    }
+}
+
+#[derive(Debug)]
+pub struct DeviceInfo {
+    pub path: PathBuf,
+    pub name: String,
+    pub phys: Option<String>,
+    pub uniq: Option<String>,
+    pub input_id: InputId,
+    pub keys: Vec<Key>,
+    pub axes: Vec<AbsoluteAxisType>,
+    pub grabbed: bool,
+    /// Accelerometer or gyro
+    pub motion: bool,
+    pub connected: Instant,
+    pub events: u64,
+}
+
+impl DeviceInfo {
+    pub fn new(path: &Path, dev: &Device, grabbed: bool) -> DeviceInfo {
+        DeviceInfo {
+            path: path.to_path_buf(),
+            name: dev.name().unwrap_or("<invalid name>").to_string(),
+            phys: dev.physical_path().map(|p| p.to_string()),
+            uniq: dev.unique_name().filter(|u| !u.is_empty()).map(|u| u.to_string()),
+            input_id: dev.input_id(),
+            keys: dev.supported_keys().map_or(Vec::new(), |k| k.iter().collect()),
+            axes: dev.supported_absolute_axes().map_or(Vec::new(), |a| a.iter().collect()),
+            grabbed,
+            motion: dev.properties().contains(PropType::ACCELEROMETER),
+            connected: Instant::now(),
+            events: 0,
+        }
+    }
+}
+
+impl fmt::Display for DeviceInfo {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} at {} [{:04x}:{:04x}, {:?}], {} keys, {} axes, {}, connected for {}s, {} events",
+            self.name, self.path.display(),
+            self.input_id.vendor(), self.input_id.product(), self.input_id.bus_type(),
+            self.keys.len(), self.axes.len(),
+            if self.grabbed { "grabbed" } else { "not grabbed" },
+            self.connected.elapsed().as_secs(), self.events)
+    }
+}
+
+/// Every physical device rinputer is currently reading from
+#[derive(Debug, Default)]
+pub struct Registry {
+    devices: HashMap<DeviceId, DeviceInfo>,
+}
+
+impl Registry {
+    pub fn new() -> Registry {
+        Registry::default()
+    }
+
+    pub fn connect(&mut self, id: DeviceId, info: DeviceInfo) {
+        println!("Device {} connected: {}", id, info);
+        self.devices.insert(id, info);
+    }
+
+    pub fn disconnect(&mut self, id: DeviceId) -> Option<DeviceInfo> {
+        let info = self.devices.remove(&id);
+        if let Some(ref info) = info {
+            println!("Device {} disconnected: {}", id, info);
+        }
+        info
+    }
+
+    pub fn get(&self, id: DeviceId) -> Option<&DeviceInfo> {
+        self.devices.get(&id)
+    }
+
+    pub fn count_event(&mut self, id: DeviceId) {
+        if let Some(info) = self.devices.get_mut(&id) {
+            info.events += 1;
+        }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&DeviceId, &DeviceInfo)> {
+        let mut devices: Vec<_> = self.devices.iter().collect();
+        devices.sort_by_key(|(id, _)| **id);
+        devices.into_iter()
+    }
+}
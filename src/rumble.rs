@@ -0,0 +1,157 @@
+use std::collections::HashMap;
+use std::os::unix::io::AsRawFd;
+use std::sync::mpsc::Sender;
+use std::sync::{Arc, Mutex};
+
+use anyhow::Result;
+use nix::poll::{poll, PollFd, PollFlags};
+
+use evdev::FFEffect;
+use evdev::FFEffectData;
+use evdev::FFEffectKind;
+use evdev::FFEffectType;
+use evdev::FFReplay;
+use evdev::FFTrigger;
+use evdev::InputEventKind;
+use evdev::UInputEventType;
+use evdev::uinput::VirtualDevice;
+
+use crate::RinputerEvent;
+use crate::filter::DeviceMatch;
+use crate::players::Players;
+use crate::registry::DeviceId;
+use crate::registry::DeviceInfo;
+
+/// A rumble effect a game asked a virtual pad to play, magnitudes go up to 0xffff
+#[derive(Debug, Clone, Copy)]
+pub struct RumbleEffect {
+    pub strong: u16,
+    pub weak: u16,
+    /// In milliseconds, 0 plays until stopped
+    pub length: u16,
+}
+
+impl RumbleEffect {
+    fn scaled(self, factor: f32) -> RumbleEffect {
+        let scale = |m: u16| (m as f32 * factor).round().clamp(0.0, u16::MAX as f32) as u16;
+        RumbleEffect { strong: scale(self.strong), weak: scale(self.weak), length: self.length }
+    }
+
+    pub fn data(self) -> FFEffectData {
+        FFEffectData {
+            direction: 0,
+            trigger: FFTrigger { button: 0, interval: 0 },
+            replay: FFReplay { length: self.length, delay: 0 },
+            kind: FFEffectKind::Rumble {
+                strong_magnitude: self.strong,
+                weak_magnitude: self.weak,
+            },
+        }
+    }
+}
+
+/// Handles force feedback requests games send to the virtual pad in `slot`
+///
+/// Effects are only kept here, playing and stopping them is sent to the main loop as
+/// `RinputerEvent::Rumble`, which forwards it to physical devices.
+pub fn ff_watcher(tx: Sender<RinputerEvent>, slot: usize, pad: Arc<Mutex<VirtualDevice>>) -> Result<()> {
+    let fd = pad.lock().unwrap().as_raw_fd();
+    let mut effects: HashMap<u16, RumbleEffect> = HashMap::new();
+    let mut playing: Option<u16> = None;
+    let mut gain: f32 = 1.0;
+
+    loop {
+        // wait without holding the lock, main loop needs it to emit events
+        poll(&mut [PollFd::new(fd, PollFlags::POLLIN)], -1)?;
+
+        let mut pad = pad.lock().unwrap();
+        let events: Vec<_> = pad.fetch_events()?.collect();
+        for ev in events {
+            match ev.kind() {
+                InputEventKind::UInput(code) if code == UInputEventType::UI_FF_UPLOAD.0 => {
+                    let mut upload = pad.process_ff_upload(ev)?;
+                    // only FF_RUMBLE is advertised, kernel turns down everything else
+                    if let FFEffectKind::Rumble { strong_magnitude, weak_magnitude } = upload.effect().kind {
+                        effects.insert(upload.effect_id() as u16, RumbleEffect {
+                            strong: strong_magnitude,
+                            weak: weak_magnitude,
+                            length: upload.effect().replay.length,
+                        });
+                    }
+                    upload.set_retval(0);
+                },
+                InputEventKind::UInput(code) if code == UInputEventType::UI_FF_ERASE.0 => {
+                    let mut erase = pad.process_ff_erase(ev)?;
+                    let id = erase.effect_id() as u16;
+                    effects.remove(&id);
+                    if playing == Some(id) {
+                        playing = None;
+                        tx.send(RinputerEvent::Rumble(slot, None))?;
+                    }
+                    erase.set_retval(0);
+                },
+                InputEventKind::ForceFeedback(code) if code == FFEffectType::FF_GAIN.0 => {
+                    gain = ev.value() as f32 / u16::MAX as f32;
+                },
+                InputEventKind::ForceFeedback(id) => {
+                    if ev.value() > 0 {
+                        if let Some(effect) = effects.get(&id) {
+                            playing = Some(id);
+                            tx.send(RinputerEvent::Rumble(slot, Some(effect.scaled(gain))))?;
+                        }
+                    } else if playing == Some(id) {
+                        playing = None;
+                        tx.send(RinputerEvent::Rumble(slot, None))?;
+                    }
+                },
+                _ => (),
+            }
+        }
+    }
+}
+
+struct Motor {
+    effect: FFEffect,
+    strength: f32,
+}
+
+/// Physical devices that can rumble, each with a single effect that gets rewritten on every request
+pub struct Rumble {
+    strength: Vec<(DeviceMatch, f32)>,
+    motors: HashMap<DeviceId, Motor>,
+}
+
+impl Rumble {
+    pub fn new(strength: Vec<(DeviceMatch, f32)>) -> Rumble {
+        Rumble {
+            strength,
+            motors: HashMap::new(),
+        }
+    }
+
+    pub fn connect(&mut self, id: DeviceId, effect: FFEffect, info: &DeviceInfo) {
+        let strength = self.strength.iter()
+            .find(|(m, _)| m.matches_info(info))
+            .map_or(1.0, |(_, s)| *s);
+        println!("Device {} can rumble, strength {}", id, strength);
+        self.motors.insert(id, Motor { effect, strength });
+    }
+
+    pub fn disconnect(&mut self, id: DeviceId) {
+        self.motors.remove(&id);
+    }
+
+    /// Plays `effect` on every device driving the pad in `slot`, or stops them on None
+    pub fn play(&mut self, slot: usize, effect: Option<RumbleEffect>, players: &Players) {
+        for (id, motor) in self.motors.iter_mut().filter(|(id, _)| players.slot(**id) == slot) {
+            let res = match effect {
+                Some(effect) if motor.strength > 0.0 => motor.effect.update(effect.scaled(motor.strength).data())
+                    .and_then(|_| motor.effect.play(1)),
+                _ => motor.effect.stop(),
+            };
+            if let Err(e) = res {
+                eprintln!("Failed to rumble device {}: {}", id, e);
+            }
+        }
+    }
+}
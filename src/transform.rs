@@ -0,0 +1,113 @@
+use serde::{Deserialize, Serialize};
+
+use evdev::AbsoluteAxisType;
+
+use crate::output_range;
+use crate::MIN_OUT_ANALOG;
+use crate::MAX_OUT_ANALOG;
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub enum Stick {
+    Left,
+    Right,
+}
+
+impl Stick {
+    pub fn axes(self) -> (AbsoluteAxisType, AbsoluteAxisType) {
+        match self {
+            Stick::Left => (AbsoluteAxisType::ABS_X, AbsoluteAxisType::ABS_Y),
+            Stick::Right => (AbsoluteAxisType::ABS_RX, AbsoluteAxisType::ABS_RY),
+        }
+    }
+}
+
+/// Fixes up controllers with odd layouts, applied in order before deadzones
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum AxisTransform {
+    Invert(AbsoluteAxisType),
+    SwapXY(Stick),
+    SwapSticks,
+    /// Rotates the stick clockwise, in degrees
+    Rotate(Stick, f32),
+}
+
+enum Step {
+    Invert(AbsoluteAxisType),
+    Swap(AbsoluteAxisType, AbsoluteAxisType),
+    Rotate {
+        x_axis: AbsoluteAxisType,
+        y_axis: AbsoluteAxisType,
+        sin: f32,
+        cos: f32,
+        x: i32,
+        y: i32,
+    },
+}
+
+impl Step {
+    fn apply(&mut self, axis: AbsoluteAxisType, value: i32, out: &mut Vec<(AbsoluteAxisType, i32)>) {
+        match self {
+            Step::Invert(a) if *a == axis => {
+                let (min, max) = output_range(axis);
+                out.push((axis, (min + max - value).clamp(min, max)));
+            },
+            Step::Swap(a, b) if *a == axis => out.push((*b, value)),
+            Step::Swap(a, b) if *b == axis => out.push((*a, value)),
+            Step::Rotate { x_axis, y_axis, sin, cos, x, y } if axis == *x_axis || axis == *y_axis => {
+                if axis == *x_axis {
+                    *x = value;
+                } else {
+                    *y = value;
+                }
+                let (fx, fy) = (*x as f32, *y as f32);
+                let rx = (fx * *cos - fy * *sin).round() as i32;
+                let ry = (fx * *sin + fy * *cos).round() as i32;
+                out.push((*x_axis, rx.clamp(MIN_OUT_ANALOG, MAX_OUT_ANALOG)));
+                out.push((*y_axis, ry.clamp(MIN_OUT_ANALOG, MAX_OUT_ANALOG)));
+            },
+            _ => out.push((axis, value)),
+        }
+    }
+}
+
+/// Per-device transform state, works on already calibrated values
+pub struct Transforms {
+    steps: Vec<Step>,
+}
+
+impl Transforms {
+    pub fn new(config: &[AxisTransform]) -> Transforms {
+        let mut steps = Vec::new();
+        for t in config {
+            match *t {
+                AxisTransform::Invert(a) => steps.push(Step::Invert(a)),
+                AxisTransform::SwapXY(stick) => {
+                    let (x_axis, y_axis) = stick.axes();
+                    steps.push(Step::Swap(x_axis, y_axis));
+                },
+                AxisTransform::SwapSticks => {
+                    steps.push(Step::Swap(AbsoluteAxisType::ABS_X, AbsoluteAxisType::ABS_RX));
+                    steps.push(Step::Swap(AbsoluteAxisType::ABS_Y, AbsoluteAxisType::ABS_RY));
+                },
+                AxisTransform::Rotate(stick, degrees) => {
+                    let (x_axis, y_axis) = stick.axes();
+                    let (sin, cos) = degrees.to_radians().sin_cos();
+                    steps.push(Step::Rotate { x_axis, y_axis, sin, cos, x: 0, y: 0 });
+                },
+            }
+        }
+        Transforms { steps }
+    }
+
+    pub fn process(&mut self, axis: AbsoluteAxisType, value: i32) -> Vec<(AbsoluteAxisType, i32)> {
+        let mut events = vec![(axis, value)];
+        for step in self.steps.iter_mut() {
+            let mut out = Vec::with_capacity(events.len());
+            for (a, v) in events {
+                step.apply(a, v, &mut out);
+            }
+            events = out;
+        }
+        events
+    }
+}